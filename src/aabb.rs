@@ -1,6 +1,6 @@
 use crate::*;
 
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone, Default, Debug)]
 pub struct Aabb {
   minimum: point3,
   maximum: point3,
@@ -13,9 +13,9 @@ pub fn surrounding_box(box0: &Aabb, box1: &Aabb) -> Aabb {
   );
 
   let big = point3::from(
-    max(box0.min().x(), box1.min().x()),
-    max(box0.min().y(), box1.min().y()),
-    max(box0.min().z(), box1.min().z()),
+    max(box0.max().x(), box1.max().x()),
+    max(box0.max().y(), box1.max().y()),
+    max(box0.max().z(), box1.max().z()),
   );
 
   Aabb::from(&small, &big)
@@ -39,7 +39,16 @@ impl Aabb {
   pub fn max(&self) -> point3 {
     self.maximum
   }
-  pub fn hit(&self, r: &Ray, mut t_min: f32, mut t_max: f32) -> bool {
+  pub fn contains(&self, p: &point3, tolerance: f32) -> bool {
+    (0..3).all(|i| {
+      p[i] >= self.minimum[i] - tolerance && p[i] <= self.maximum[i] + tolerance
+    })
+  }
+  pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+    self.hit_interval(r, t_min, t_max).is_some()
+  }
+  /// Returns the parametric range `(t_enter, t_exit)` over which the ray is inside the box
+  pub fn hit_interval(&self, r: &Ray, mut t_min: f32, mut t_max: f32) -> Option<(f32, f32)> {
     for i in 0..3 {
       let inv_d = 1.0 / r.direction()[i];
 
@@ -55,10 +64,10 @@ impl Aabb {
       t_max = if t1 < t_max { t1 } else { t_max };
 
       if t_max <= t_min {
-        return false;
+        return None;
       }
     }
-    true
+    Some((t_min, t_max))
   }
 }
 
@@ -86,3 +95,17 @@ fn max(a: f32, b: f32) -> f32 {
     return b;
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn surrounding_box_spans_both_boxes() {
+    let a = Aabb::from(&point3::from(0., 0., 0.), &point3::from(1., 1., 1.));
+    let b = Aabb::from(&point3::from(2., -1., 0.5), &point3::from(3., 0.5, 4.));
+    let both = surrounding_box(&a, &b);
+    assert!((both.min() - point3::from(0., -1., 0.)).len() < 1e-6);
+    assert!((both.max() - point3::from(3., 1., 4.)).len() < 1e-6);
+  }
+}
@@ -34,35 +34,33 @@ impl XyRect {
 }
 
 impl Hittable for XyRect {
-  fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-    *output_box = Aabb::from(
+  fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+    Some(Aabb::from(
       &point3::from(self.x0, self.y0, self.k - 0.0001),
       &point3::from(self.x1, self.y1, self.k + 0.0001),
-    );
-    true
+    ))
   }
-  fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+  fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
     let t = (self.k - r.origin().z()) / r.direction().z();
     if t < t_min || t > t_max {
-      return false;
+      return None;
     }
     let x = r.origin().x() + t * r.direction().x();
     let y = r.origin().y() + t * r.direction().y();
     if x < self.x0 || x > self.x1 || y < self.y0 || y > self.y1 {
-      return false;
+      return None;
     }
 
-    rec.u = (x - self.x0) / (self.x1 - self.x0);
-    rec.v = (x - self.y0) / (self.y1 - self.y0);
-
-    rec.t = t;
+    let u = (x - self.x0) / (self.x1 - self.x0);
+    let v = (y - self.y0) / (self.y1 - self.y0);
 
     let outward_normal = Vec3::from(0., 0., 1.);
-    rec.set_face_normal(*r, outward_normal);
-    rec.mat_ptr = &*self.mp;
-    rec.p = r.at(t);
-
-    true
+    Some(
+      HitRecord::from(r, t, outward_normal, (u, v), &*self.mp).with_tangents((
+        Vec3::from(self.x1 - self.x0, 0., 0.),
+        Vec3::from(0., self.y1 - self.y0, 0.),
+      )),
+    )
   }
 }
 
@@ -99,35 +97,33 @@ impl XzRect {
 }
 
 impl Hittable for XzRect {
-  fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-    *output_box = Aabb::from(
+  fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+    Some(Aabb::from(
       &point3::from(self.x0, self.k - 0.0001, self.z0),
       &point3::from(self.x1, self.k + 0.0001, self.z1),
-    );
-    true
+    ))
   }
-  fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+  fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
     let t = (self.k - r.origin().y()) / r.direction().y();
     if t < t_min || t > t_max {
-      return false;
+      return None;
     }
     let x = r.origin().x() + t * r.direction().x();
     let z = r.origin().z() + t * r.direction().z();
     if x < self.x0 || x > self.x1 || z < self.z0 || z > self.z1 {
-      return false;
+      return None;
     }
 
-    rec.u = (x - self.x0) / (self.x1 - self.x0);
-    rec.v = (z - self.z0) / (self.z1 - self.z0);
-
-    rec.t = t;
-
-    let outward_normal = Vec3::from(0., 0., 1.);
-    rec.set_face_normal(*r, outward_normal);
-    rec.mat_ptr = &*self.mp;
-    rec.p = r.at(t);
+    let u = (x - self.x0) / (self.x1 - self.x0);
+    let v = (z - self.z0) / (self.z1 - self.z0);
 
-    true
+    let outward_normal = Vec3::from(0., 1., 0.);
+    Some(
+      HitRecord::from(r, t, outward_normal, (u, v), &*self.mp).with_tangents((
+        Vec3::from(self.x1 - self.x0, 0., 0.),
+        Vec3::from(0., 0., self.z1 - self.z0),
+      )),
+    )
   }
 }
 
@@ -164,34 +160,32 @@ impl YzRect {
 }
 
 impl Hittable for YzRect {
-  fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-    *output_box = Aabb::from(
+  fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+    Some(Aabb::from(
       &point3::from(self.k - 0.0001, self.y0, self.z0),
       &point3::from(self.k + 0.0001, self.y1, self.z1),
-    );
-    true
+    ))
   }
-  fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+  fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
     let t = (self.k - r.origin().x()) / r.direction().x();
     if t < t_min || t > t_max {
-      return false;
+      return None;
     }
     let y = r.origin().y() + t * r.direction().y();
     let z = r.origin().z() + t * r.direction().z();
     if y < self.y0 || y > self.y1 || z < self.z0 || z > self.z1 {
-      return false;
+      return None;
     }
 
-    rec.u = (y - self.y0) / (self.y1 - self.y0);
-    rec.v = (z - self.z0) / (self.z1 - self.z0);
-
-    rec.t = t;
-
-    let outward_normal = Vec3::from(0., 0., 1.);
-    rec.set_face_normal(*r, outward_normal);
-    rec.mat_ptr = &*self.mp;
-    rec.p = r.at(t);
+    let u = (y - self.y0) / (self.y1 - self.y0);
+    let v = (z - self.z0) / (self.z1 - self.z0);
 
-    true
+    let outward_normal = Vec3::from(1., 0., 0.);
+    Some(
+      HitRecord::from(r, t, outward_normal, (u, v), &*self.mp).with_tangents((
+        Vec3::from(0., self.y1 - self.y0, 0.),
+        Vec3::from(0., 0., self.z1 - self.z0),
+      )),
+    )
   }
 }
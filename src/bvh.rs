@@ -1,17 +1,39 @@
 use crate::{aabb::*, objects::hittable::*, objects::hittable_list::*, ray::*, utils::*};
+use std::cmp::Ordering;
 use std::ops::Deref;
 use std::sync::Arc;
 
 pub struct BvhNode {
   left: Arc<dyn Hittable>,
   right: Arc<dyn Hittable>,
-  boxx: Aabb,
+  /// `None` for a tree over no bounded objects
+  boxx: Option<Aabb>,
+  /// Objects without a bounding box, such as planes, tested beside the tree
+  unbounded: Vec<Arc<dyn Hittable>>,
 }
 
 impl BvhNode {
   pub fn from(list: &mut HittableList, time0: f32, time1: f32) -> Self {
-    let len = &mut list.objects.len();
-    Self::fromvec(&mut list.objects, 0, *len, time0, time1)
+    let (mut bounded, unbounded): (Vec<_>, Vec<_>) = list
+      .objects
+      .iter()
+      .cloned()
+      .partition(|object| object.bounding_box(time0, time1).is_some());
+
+    if bounded.is_empty() {
+      let empty: Arc<dyn Hittable> = Arc::new(HittableList::new());
+      return Self {
+        left: empty.clone(),
+        right: empty,
+        boxx: None,
+        unbounded,
+      };
+    }
+    let len = bounded.len();
+    Self {
+      unbounded,
+      ..Self::fromvec(&mut bounded, 0, len, time0, time1)
+    }
   }
   pub fn fromvec(
     src_objects: &mut Vec<Arc<dyn Hittable>>,
@@ -21,10 +43,7 @@ impl BvhNode {
     time1: f32,
   ) -> Self {
     let objects = src_objects;
-    let axis = random_int(0, 2);
-
-    let mut left: Arc<dyn Hittable> = objects[start].clone();
-    let mut right: Arc<dyn Hittable> = objects[start].clone();
+    let axis = random_int(0, 3);
 
     let comparator = if axis == 0 {
       box_x_compare
@@ -35,90 +54,144 @@ impl BvhNode {
     };
     let object_span = end - start;
 
-    if object_span == 1 {
-    } else if object_span == 1 {
-      if comparator(objects[start].deref(), objects[start + 1].deref()) {
-        left = objects[start].clone();
-        right = objects[start + 1].clone();
+    let (left, right): (Arc<dyn Hittable>, Arc<dyn Hittable>) = if object_span == 1 {
+      (objects[start].clone(), objects[start].clone())
+    } else if object_span == 2 {
+      if comparator(objects[start].deref(), objects[start + 1].deref()) == Ordering::Less {
+        (objects[start].clone(), objects[start + 1].clone())
       } else {
-        right = objects[start].clone();
-        left = objects[start + 1].clone();
+        (objects[start + 1].clone(), objects[start].clone())
       }
     } else {
-      &objects[start..end].sort_unstable_by(|a, b| {
-        if comparator(&**a, &**b) {
-          std::cmp::Ordering::Greater
-        } else {
-          std::cmp::Ordering::Less
-        }
-      });
+      objects[start..end].sort_unstable_by(|a, b| comparator(&**a, &**b));
 
       let mid = start + object_span / 2;
-      left = Arc::new(BvhNode::fromvec(objects, start, mid, time0, time1)) as Arc<dyn Hittable>;
-    }
-    let mut box_left = Aabb::new();
-    let mut box_right = Aabb::new();
+      (
+        Arc::new(BvhNode::fromvec(objects, start, mid, time0, time1)),
+        Arc::new(BvhNode::fromvec(objects, mid, end, time0, time1)),
+      )
+    };
+
+    let boxx = match (
+      left.bounding_box(time0, time1),
+      right.bounding_box(time0, time1),
+    ) {
+      (Some(box_left), Some(box_right)) => surrounding_box(&box_left, &box_right),
+      _ => {
+        eprintln!("No bounding box in bvh_node constructor");
+        Aabb::new()
+      }
+    };
 
-    if !left.bounding_box(time0, time1, &mut box_left)
-      || !right.bounding_box(time0, time1, &mut box_right)
-    {
-      eprintln!("No bounding box in bvh_node constructor");
+    Self {
+      left,
+      right,
+      boxx: Some(boxx),
+      unbounded: vec![],
     }
+  }
 
-    let boxx = surrounding_box(&box_left, &box_right);
+  fn hit_tree(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+    match self.boxx {
+      Some(boxx) if boxx.hit(r, t_min, t_max) => {}
+      _ => return None,
+    }
 
-    Self {
-      left: left.clone(),
-      right: right.clone(),
-      boxx,
+    let hit_left = self.left.hit(r, t_min, t_max);
+    if let Some(rec) = &hit_left {
+      validate_hit(&*self.left, r, rec);
     }
+    let hit_right = self
+      .right
+      .hit(r, t_min, hit_left.as_ref().map_or(t_max, |rec| rec.t));
+    if let Some(rec) = &hit_right {
+      validate_hit(&*self.right, r, rec);
+    }
+
+    hit_right.or(hit_left)
   }
 }
 
 impl Hittable for BvhNode {
-  fn bounding_box<'a>(&'a self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-    *output_box = self.boxx;
-    true
+  fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+    if !self.unbounded.is_empty() {
+      return None;
+    }
+    self.boxx
   }
-  fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
-    if !self.boxx.hit(r, t_min, t_max) {
-      return false;
+  fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+    let mut closest = self.hit_tree(r, t_min, t_max);
+    for object in &self.unbounded {
+      let closest_so_far = closest.as_ref().map_or(t_max, |rec| rec.t);
+      if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+        validate_hit(&**object, r, &rec);
+        closest = Some(rec);
+      }
     }
-
-    let hit_left = self.left.hit(r, t_min, t_max, rec);
-    let hit_right = self.right.hit(
-      r,
-      t_min,
-      {
-        if hit_left {
-          rec.t
-        } else {
-          t_max
-        }
-      },
-      rec,
-    );
-
-    hit_left || hit_right
+    closest
   }
 }
 
-fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: usize) -> bool {
-  let mut box_a = Aabb::new();
-  let mut box_b = Aabb::new();
-
-  if !a.bounding_box(0., 0., &mut box_a) || !b.bounding_box(0., 0., &mut box_b) {
-    eprintln!("No bounding box in BvhNode constructor");
+fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: usize) -> Ordering {
+  match (a.bounding_box(0., 0.), b.bounding_box(0., 0.)) {
+    (Some(box_a), Some(box_b)) => box_a.min().e[axis]
+      .partial_cmp(&box_b.min().e[axis])
+      .unwrap_or(Ordering::Equal),
+    _ => {
+      eprintln!("No bounding box in BvhNode constructor");
+      Ordering::Equal
+    }
   }
-  box_a.min().e[axis] < box_b.min().e[axis]
 }
 
-fn box_x_compare(a: &dyn Hittable, b: &dyn Hittable) -> bool {
+fn box_x_compare(a: &dyn Hittable, b: &dyn Hittable) -> Ordering {
   box_compare(a, b, 0)
 }
-fn box_y_compare(a: &dyn Hittable, b: &dyn Hittable) -> bool {
+fn box_y_compare(a: &dyn Hittable, b: &dyn Hittable) -> Ordering {
   box_compare(a, b, 1)
 }
-fn box_z_compare(a: &dyn Hittable, b: &dyn Hittable) -> bool {
+fn box_z_compare(a: &dyn Hittable, b: &dyn Hittable) -> Ordering {
   box_compare(a, b, 2)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{material::material::Lambertian, objects::sphere::Sphere, vec3::*};
+
+  #[test]
+  fn tree_keeps_every_object() {
+    for n in 1..8 {
+      let mut list = HittableList::new();
+      for i in 0..n {
+        list.add(Arc::new(Sphere::new(
+          point3::from(3. * i as f32, 0., 0.),
+          1.,
+          Arc::new(Lambertian::from(color::new())),
+        )));
+      }
+      let bvh = BvhNode::from(&mut list, 0., 1.);
+
+      let bbox = bvh.bounding_box(0., 1.).unwrap();
+      assert!((bbox.min() - point3::from(-1., -1., -1.)).len() < 1e-6);
+      assert!((bbox.max() - point3::from(3. * (n - 1) as f32 + 1., 1., 1.)).len() < 1e-6);
+      for i in 0..n {
+        let r = Ray::new(
+          point3::from(3. * i as f32, 5., 0.),
+          Vec3::from(0., -1., 0.),
+          0.,
+        );
+        let rec = bvh.hit(&r, 0.001, f32::INFINITY).unwrap();
+        assert!((rec.t - 4.).abs() < 1e-4, "{} of {}", i, n);
+      }
+    }
+  }
+
+  #[test]
+  fn empty_tree_has_no_box() {
+    let bvh = BvhNode::from(&mut HittableList::new(), 0., 1.);
+    assert!(bvh.bounding_box(0., 1.).is_none());
+    let r = Ray::new(point3::new(), Vec3::from(0., 0., -1.), 0.);
+    assert!(bvh.hit(&r, 0.001, f32::INFINITY).is_none());
+  }
+}
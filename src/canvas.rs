@@ -1,4 +1,5 @@
 use crate::colors::write_color_to_writer;
+use crate::spectrum::xyz_to_srgb;
 use crate::vec3::color;
 use rayon::prelude::*;
 
@@ -99,6 +100,11 @@ impl Canvas {
             ysize: y,
         }
     }
+    /// Converts pixels accumulated in CIE XYZ by a spectral render to linear sRGB
+    pub fn xyz_to_srgb(mut self) -> Self {
+        self.pixels.iter_mut().for_each(|p| *p = xyz_to_srgb(*p));
+        self
+    }
     pub fn write_pixels(&self) {
         let stdout = stdout();
         let mut locked = stdout.lock();
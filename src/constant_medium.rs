@@ -1,5 +1,6 @@
 use crate::{
-  aabb::*, material::material::*, objects::hittable::*, ray::*, texture::*, utils::*, vec3::*,
+  aabb::*, material::material::*, objects::hittable::*, phase::*, ray::*, texture::*, utils::*,
+  vec3::*,
 };
 use std::sync::Arc;
 
@@ -24,73 +25,75 @@ impl ConstantMedium {
       phase_function: Arc::new(Isotropic::from_color(c)),
     }
   }
+  pub fn from_phase(b: Arc<dyn Hittable>, d: f32, c: color, phase: Arc<dyn PhaseFunction>) -> Self {
+    Self {
+      boundary: b,
+      neg_inv_density: -1. / d,
+      phase_function: Arc::new(Anisotropic::from_color(c, phase)),
+    }
+  }
+}
+
+/// Distance to the next collision in a medium of density `-1 / neg_inv_density`
+pub(crate) fn sample_free_path(neg_inv_density: f32) -> f32 {
+  neg_inv_density * random_double(0., 1.).ln()
 }
 
 impl Hittable for ConstantMedium {
-  fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+  fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
     let enable_debug = false;
     let debugging = enable_debug && random_double(0., 1.) < 0.00001;
 
-    let mut rec1 = HitRecord::void();
-    let mut rec2 = HitRecord::void();
-
-    if !self
+    let rec1 = self
       .boundary
-      .hit(r, std::f32::NEG_INFINITY, std::f32::INFINITY, &mut rec1)
-    {
-      return false;
-    }
-    if !self
-      .boundary
-      .hit(r, rec1.t + 0.00001, std::f32::INFINITY, &mut rec2)
-    {
-      return false;
-    }
+      .hit(r, std::f32::NEG_INFINITY, std::f32::INFINITY)?;
+    let rec2 = self.boundary.hit(r, rec1.t + 0.00001, std::f32::INFINITY)?;
 
     if debugging {
       eprintln!("\nt_min={}, t_max={}", rec1.t, rec2.t);
     }
 
-    if rec1.t < t_min {
-      rec1.t = t_min;
-    }
-    if rec2.t > t_max {
-      rec2.t = t_max;
-    }
-
-    if rec1.t >= rec2.t {
-      return false;
-    }
+    let t_enter = rec1.t.max(t_min).max(0.);
+    let t_exit = rec2.t.min(t_max);
 
-    if rec1.t < 0. {
-      rec1.t = 0.;
+    if t_enter >= t_exit {
+      return None;
     }
 
     let ray_length = r.direction().len();
-    let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-    let hit_distance = self.neg_inv_density * random_double(0., 1.).ln();
+    let distance_inside_boundary = (t_exit - t_enter) * ray_length;
+    let hit_distance = sample_free_path(self.neg_inv_density);
 
     if hit_distance > distance_inside_boundary {
-      return false;
+      return None;
     }
-    rec.t = rec1.t + hit_distance / ray_length;
-    rec.p = r.at(rec.t);
+    let t = t_enter + hit_distance / ray_length;
 
     if debugging {
       eprintln!(
         "hit_distance = {}\n
                 rec.t         = {}\n
                 rec.p         = {:?}",
-        hit_distance, rec.t, rec.p
+        hit_distance,
+        t,
+        r.at(t)
       );
     }
-    rec.normal = Vec3::from(1., 0., 0.);
-    rec.front_face = true;
-    rec.mat_ptr = &*self.phase_function;
 
-    true
+    // The normal and face are arbitrary inside a medium
+    Some(HitRecord {
+      p: r.at(t),
+      normal: Vec3::from(1., 0., 0.),
+      mat_ptr: &*self.phase_function,
+      t,
+      u: 0.,
+      v: 0.,
+      dpdu: Vec3::new(),
+      dpdv: Vec3::new(),
+      front_face: true,
+    })
   }
-  fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-    self.boundary.bounding_box(time0, time1, output_box)
+  fn bounding_box(&self, time0: f32, time1: f32) -> Option<Aabb> {
+    self.boundary.bounding_box(time0, time1)
   }
 }
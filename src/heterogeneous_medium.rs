@@ -0,0 +1,340 @@
+use crate::{
+  aabb::*, material::material::*, objects::hittable::*, perlin::*, phase::*, ray::*, utils::*,
+  vec3::*,
+};
+use std::convert::TryInto;
+use std::fs;
+use std::sync::Arc;
+
+pub trait DensityField: Send + Sync {
+  fn density(&self, p: &point3) -> f32;
+  /// Upper bound of `density` over the whole field, used as the tracking majorant
+  fn max_density(&self) -> f32;
+}
+
+/// Densities sampled on a regular 3D grid spanning `bounds`, x varying fastest
+pub struct DensityGrid {
+  nx: usize,
+  ny: usize,
+  nz: usize,
+  data: Vec<f32>,
+  bounds: Aabb,
+  max: f32,
+}
+
+impl Default for DensityGrid {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl DensityGrid {
+  pub fn new() -> Self {
+    Self::from(0, 0, 0, vec![], Aabb::new())
+  }
+  pub fn from(nx: usize, ny: usize, nz: usize, data: Vec<f32>, bounds: Aabb) -> Self {
+    let count = nx.checked_mul(ny).and_then(|n| n.checked_mul(nz));
+    if count != Some(data.len()) {
+      eprintln!(
+        "ERROR: density grid of {}x{}x{} does not match its {} values",
+        nx,
+        ny,
+        nz,
+        data.len()
+      );
+      return Self {
+        nx: 0,
+        ny: 0,
+        nz: 0,
+        data: vec![],
+        bounds,
+        max: 0.,
+      };
+    }
+    let max = data.iter().cloned().fold(0., f32::max);
+    Self {
+      nx,
+      ny,
+      nz,
+      data,
+      bounds,
+      max,
+    }
+  }
+  /// Loads a single channel float32 Mitsuba `.vol` file, using the bounding box it stores
+  pub fn from_vol_file(filename: &str) -> Result<Self, String> {
+    let bytes = fs::read(filename)
+      .map_err(|e| format!("Could not read volume file `{}`: {}", filename, e))?;
+    if bytes.len() < 48 || &bytes[0..3] != b"VOL" || bytes[3] != 3 {
+      return Err(format!("`{}` is not a version 3 .vol file", filename));
+    }
+
+    let int_at = |i: usize| i32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+    let float_at = |i: usize| f32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+
+    let (encoding, channels) = (int_at(4), int_at(20));
+    if encoding != 1 || channels != 1 {
+      return Err(format!(
+        "`{}` must hold a single float32 channel (encoding {}, {} channels)",
+        filename, encoding, channels
+      ));
+    }
+    let (nx, ny, nz) = (int_at(8), int_at(12), int_at(16));
+    if nx <= 0 || ny <= 0 || nz <= 0 {
+      return Err(format!(
+        "`{}` has an invalid resolution of {}x{}x{}",
+        filename, nx, ny, nz
+      ));
+    }
+    let (nx, ny, nz) = (nx as usize, ny as usize, nz as usize);
+    let count = nx
+      .checked_mul(ny)
+      .and_then(|n| n.checked_mul(nz))
+      .ok_or_else(|| format!("`{}` has too many voxels", filename))?;
+    let data: Vec<f32> = bytes[48..]
+      .chunks_exact(4)
+      .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+      .collect();
+    if data.len() != count {
+      return Err(format!(
+        "`{}` needs {} values for {}x{}x{}, got {}",
+        filename,
+        count,
+        nx,
+        ny,
+        nz,
+        data.len()
+      ));
+    }
+    let bounds = Aabb::from(
+      &point3::from(float_at(24), float_at(28), float_at(32)),
+      &point3::from(float_at(36), float_at(40), float_at(44)),
+    );
+
+    Ok(Self::from(nx, ny, nz, data, bounds))
+  }
+  /// Loads a headerless file of one byte per voxel, mapped to densities in [0, 1]
+  pub fn from_raw_file(filename: &str, nx: usize, ny: usize, nz: usize, bounds: Aabb) -> Self {
+    match fs::read(filename) {
+      Ok(bytes) => Self::from(
+        nx,
+        ny,
+        nz,
+        bytes.iter().map(|&b| b as f32 / 255.).collect(),
+        bounds,
+      ),
+      Err(e) => {
+        eprintln!("ERROR: Could not read volume file `{}`: {}", filename, e);
+        Self::new()
+      }
+    }
+  }
+  fn voxel(&self, i: usize, j: usize, k: usize) -> f32 {
+    self.data[(k * self.ny + j) * self.nx + i]
+  }
+}
+
+impl DensityField for DensityGrid {
+  fn density(&self, p: &point3) -> f32 {
+    if self.data.is_empty() {
+      return 0.;
+    }
+    let res = [self.nx, self.ny, self.nz];
+    let mut base = [0usize; 3];
+    let mut frac = [0f32; 3];
+
+    for a in 0..3 {
+      let extent = self.bounds.max()[a] - self.bounds.min()[a];
+      let local = (p[a] - self.bounds.min()[a]) / extent;
+      if !(0. ..=1.).contains(&local) {
+        return 0.;
+      }
+      // Samples sit at voxel centers
+      let x = clamp(local * res[a] as f32 - 0.5, 0., (res[a] - 1) as f32);
+      base[a] = (x.floor() as usize).min(res[a].saturating_sub(2));
+      frac[a] = x - base[a] as f32;
+    }
+
+    let mut accum = 0.;
+    for di in 0..2 {
+      for dj in 0..2 {
+        for dk in 0..2 {
+          let i = (base[0] + di).min(self.nx - 1);
+          let j = (base[1] + dj).min(self.ny - 1);
+          let k = (base[2] + dk).min(self.nz - 1);
+          let weight = (if di == 1 { frac[0] } else { 1. - frac[0] })
+            * (if dj == 1 { frac[1] } else { 1. - frac[1] })
+            * (if dk == 1 { frac[2] } else { 1. - frac[2] });
+          accum += weight * self.voxel(i, j, k);
+        }
+      }
+    }
+    accum
+  }
+  fn max_density(&self) -> f32 {
+    self.max
+  }
+}
+
+/// Procedural density from Perlin turbulence
+pub struct NoiseDensity {
+  noise: Perlin,
+  frequency: f32,
+  density: f32,
+}
+
+impl NoiseDensity {
+  pub fn from(frequency: f32, density: f32) -> Self {
+    Self {
+      noise: Perlin::new(),
+      frequency,
+      density,
+    }
+  }
+}
+
+impl DensityField for NoiseDensity {
+  fn density(&self, p: &point3) -> f32 {
+    self.density * self.noise.turb(&(*p * self.frequency), 5)
+  }
+  fn max_density(&self) -> f32 {
+    // Turbulence never exceeds the sum of its octave weights
+    self.density * 2.
+  }
+}
+
+/// Participating medium with spatially varying density, sampled by delta tracking
+pub struct HeterogeneousMedium {
+  boundary: Arc<dyn Hittable>,
+  density: Arc<dyn DensityField>,
+  phase_function: Arc<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+  pub fn from_material(
+    b: Arc<dyn Hittable>,
+    density: Arc<dyn DensityField>,
+    phase_function: Arc<dyn Material>,
+  ) -> Self {
+    Self {
+      boundary: b,
+      density,
+      phase_function,
+    }
+  }
+  pub fn from_phase(
+    b: Arc<dyn Hittable>,
+    density: Arc<dyn DensityField>,
+    c: color,
+    phase: Arc<dyn PhaseFunction>,
+  ) -> Self {
+    Self::from_material(b, density, Arc::new(Anisotropic::from_color(c, phase)))
+  }
+}
+
+impl Hittable for HeterogeneousMedium {
+  fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+    let rec1 = self.boundary.hit(r, f32::NEG_INFINITY, f32::INFINITY)?;
+    let rec2 = self.boundary.hit(r, rec1.t + 0.00001, f32::INFINITY)?;
+
+    let t_enter = rec1.t.max(t_min).max(0.);
+    let t_exit = rec2.t.min(t_max);
+    if t_enter >= t_exit {
+      return None;
+    }
+
+    let majorant = self.density.max_density();
+    if majorant <= 0. {
+      return None;
+    }
+
+    // Delta tracking: sample free flights against the majorant and accept each tentative
+    // collision with probability density / majorant
+    let ray_length = r.direction().len();
+    let mut t = t_enter;
+    loop {
+      t -= (1. - random_double(0., 1.)).ln() / (majorant * ray_length);
+      if t >= t_exit {
+        return None;
+      }
+      let p = r.at(t);
+      if random_double(0., 1.) * majorant < self.density.density(&p) {
+        return Some(HitRecord {
+          p,
+          normal: Vec3::from(1., 0., 0.),
+          mat_ptr: &*self.phase_function,
+          t,
+          u: 0.,
+          v: 0.,
+          dpdu: Vec3::new(),
+          dpdv: Vec3::new(),
+          front_face: true,
+        });
+      }
+    }
+  }
+  fn bounding_box(&self, time0: f32, time1: f32) -> Option<Aabb> {
+    self.boundary.bounding_box(time0, time1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::objects::sphere::Sphere;
+
+  #[test]
+  fn delta_tracking_matches_beer_lambert() {
+    let bounds = Aabb::from(&point3::from(-1., -1., -1.), &point3::from(1., 1., 1.));
+    let grid = DensityGrid::from(2, 2, 2, vec![0.5; 8], bounds);
+    let boundary = Arc::new(Sphere::new(
+      point3::new(),
+      1.,
+      Arc::new(Lambertian::from(color::new())),
+    ));
+    let medium = HeterogeneousMedium::from_phase(
+      boundary,
+      Arc::new(grid),
+      color::new(),
+      Arc::new(IsotropicPhase),
+    );
+
+    let r = Ray::new(point3::from(0., 0., -5.), Vec3::from(0., 0., 1.), 0.);
+    let trials = 20000;
+    let hits = (0..trials)
+      .filter(|_| medium.hit(&r, 0.001, f32::INFINITY).is_some())
+      .count();
+
+    let expected = 1. - (-0.5f32 * 2.).exp();
+    assert!((hits as f32 / trials as f32 - expected).abs() < 0.02);
+  }
+
+  #[test]
+  fn vol_file_with_bad_resolution_is_rejected() {
+    let header = |nx: i32, ny: i32, nz: i32| {
+      let mut bytes = b"VOL".to_vec();
+      bytes.push(3);
+      for v in &[1, nx, ny, nz, 1] {
+        bytes.extend_from_slice(&i32::to_le_bytes(*v));
+      }
+      for v in &[-1f32, -1., -1., 1., 1., 1.] {
+        bytes.extend_from_slice(&f32::to_le_bytes(*v));
+      }
+      bytes
+    };
+    let path = std::env::temp_dir().join("raytracer_bad_resolution.vol");
+    let path = path.to_str().unwrap();
+    for &(nx, ny, nz) in &[(-1, 2, 2), (0, 2, 2), (i32::MAX, i32::MAX, i32::MAX)] {
+      fs::write(path, header(nx, ny, nz)).unwrap();
+      assert!(DensityGrid::from_vol_file(path).is_err());
+    }
+
+    let mut good = header(2, 1, 1);
+    good.extend_from_slice(&f32::to_le_bytes(0.25));
+    good.extend_from_slice(&f32::to_le_bytes(0.75));
+    fs::write(path, good).unwrap();
+    let grid = DensityGrid::from_vol_file(path).unwrap();
+    assert_eq!(grid.max_density(), 0.75);
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -8,25 +8,49 @@ mod camera;
 mod canvas;
 mod colors;
 mod constant_medium;
+mod heterogeneous_medium;
 mod material;
 mod objects;
+mod onb;
 mod perlin;
+mod phase;
 mod ray;
+mod spectrum;
+mod subsurface;
 mod texture;
+mod tlas;
 mod utils;
 mod vec3;
 
+pub use aabb::*;
 pub use aarect::*;
 pub use bvh::*;
 pub use camera::Camera;
 pub use canvas::Canvas;
 pub use constant_medium::*;
+pub use heterogeneous_medium::*;
 pub use material::boxx::*;
+pub use material::bump::*;
+pub use material::diffuse::*;
+pub use material::hair::*;
+pub use material::layered::*;
 pub use material::material::*;
-pub use objects::{hittable::*, hittable_list::*, moving_sphere::*, sphere::*};
+pub use material::microfacet::*;
+pub use material::mix::*;
+pub use material::principled::*;
+pub use material::thin_film::*;
+pub use objects::{
+    bezier_patch::*, curve::*, heightfield::*, hittable::*, hittable_list::*, mesh::*, metaballs::*,
+    moving_sphere::*, plane::*, scatter::*, sdf::*, sphere::*, subdivision::*,
+};
+pub use onb::*;
 pub use perlin::*;
+pub use phase::*;
 pub use ray::*;
+pub use spectrum::*;
+pub use subsurface::*;
 pub use texture::*;
+pub use tlas::*;
 pub use utils::*;
 pub use vec3::*;
 
@@ -125,22 +149,15 @@ fn final_scene() -> HittableList {
         Arc::new(Lambertian::from_texture(pertext)),
     )));
 
-    let mut boxes2 = HittableList::new();
     let white = Arc::new(Lambertian::from(color(73.)));
-    let ns = 1000;
-    for j in 0..ns {
-        boxes2.add(Arc::new(Sphere::new(
-            point3::random(0., 165.),
-            10.,
-            white.clone(),
-        )))
-    }
+    let cluster = Scatter::from(
+        Arc::new(Sphere::new(point3::new(), 10., white)),
+        ScatterDomain::Volume(Aabb::from(&point3::new(), &point3::from(165., 165., 165.))),
+        1000,
+    );
 
     objects.add(Arc::new(Translate::from(
-        Arc::new(RotateY::from(
-            Arc::new(BvhNode::from(&mut boxes2, 0., 1.)),
-            15.,
-        )),
+        Arc::new(RotateY::from(Arc::new(cluster.build()), 15.)),
         &Vec3::from(-100., 270., 395.),
     )));
 
@@ -151,6 +168,19 @@ fn color(unique: f32) -> color {
     color::from(unique, unique, unique)
 }
 
+/// Matte y = 0 plane checkered in squares of 0.3 along its uvs
+fn checker_ground(even: color, odd: color) -> Arc<dyn Hittable> {
+    let checker = Arc::new(CheckerTexture::from_colors(even, odd).with_uv_cells(2.));
+    Arc::new(
+        Plane::from(
+            point3::from(0., 0., 0.),
+            Vec3::from(0., 1., 0.),
+            Arc::new(Lambertian::from_texture(checker)),
+        )
+        .with_tile_size(0.6),
+    )
+}
+
 fn cornell_smoke() -> HittableList {
     let mut objects = HittableList::new();
 
@@ -218,6 +248,57 @@ fn cornell_smoke() -> HittableList {
     objects
 }
 
+fn cornell_clouds() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let red = Arc::new(Lambertian::from(color::from(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::from(color::from(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::from(color::from(0.12, 0.45, 0.15)));
+    let light = Arc::new(DiffuseLight::from_color(color::from(15., 15., 15.)));
+
+    objects.add(Arc::new(YzRect::from(0., 555., 0., 555., 555., green)));
+    objects.add(Arc::new(YzRect::from(0., 555., 0., 555., 0., red)));
+    objects.add(Arc::new(XzRect::from(113., 443., 127., 432., 554., light)));
+    objects.add(Arc::new(XzRect::from(
+        0.,
+        555.,
+        0.,
+        555.,
+        0.,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XzRect::from(
+        0.,
+        555.,
+        0.,
+        555.,
+        555.,
+        white.clone(),
+    )));
+    objects.add(Arc::new(XyRect::from(
+        0.,
+        555.,
+        0.,
+        555.,
+        555.,
+        white.clone(),
+    )));
+
+    let cloud_boundary: Arc<dyn Hittable> = Arc::new(Boxx::from(
+        &point3::from(80., 80., 80.),
+        &point3::from(475., 400., 475.),
+        white.clone(),
+    ));
+    objects.add(Arc::new(HeterogeneousMedium::from_phase(
+        cloud_boundary,
+        Arc::new(NoiseDensity::from(0.02, 0.03)),
+        color::from(0.9, 0.9, 0.9),
+        Arc::new(DoubleHenyeyGreenstein::from(0.7, -0.3, 0.8)),
+    )));
+
+    objects
+}
+
 fn cornell_box() -> HittableList {
     let mut objects = HittableList::new();
 
@@ -279,9 +360,9 @@ fn cornell_box() -> HittableList {
 fn simple_light() -> HittableList {
     let mut objects = HittableList::new();
     let pertext = Arc::new(NoiseTexture::from(4.));
-    objects.add(Arc::new(Sphere::new(
-        point3::from(0., -1000., 0.),
-        1000.,
+    objects.add(Arc::new(Plane::from(
+        point3::new(),
+        Vec3::from(0., 1., 0.),
         Arc::new(Lambertian::from_texture(pertext.clone())),
     )));
     objects.add(Arc::new(Sphere::new(
@@ -323,9 +404,9 @@ fn earth() -> HittableList {
 fn two_perlin_spheres() -> HittableList {
     let mut objects = HittableList::new();
     let pertext = Arc::new(NoiseTexture::from(4.));
-    objects.add(Arc::new(Sphere::new(
-        point3::from(0., -1000., 0.),
-        1000.,
+    objects.add(Arc::new(Plane::from(
+        point3::new(),
+        Vec3::from(0., 1., 0.),
         Arc::new(Lambertian::from_texture(pertext.clone())),
     )));
     objects.add(Arc::new(Sphere::new(
@@ -358,21 +439,581 @@ fn two_spheres() -> HittableList {
     objects
 }
 
-fn random_scene() -> HittableList {
-    let mut world = HittableList::new();
+fn sdf_shapes() -> HittableList {
+    let mut objects = HittableList::new();
 
-    let checker = Arc::new(CheckerTexture::from_colors(
-        color::from(0.2, 0.3, 0.1),
-        color::from(0.9, 0.9, 0.9),
+    objects.add(checker_ground(color::from(0.2, 0.3, 0.1), color::from(0.9, 0.9, 0.9)));
+
+    let rounded_box: Arc<dyn DistanceField> = Arc::new(SdfRoundBox {
+        center: point3::from(-2.5, 1., 0.),
+        half_extents: Vec3::from(0.8, 0.8, 0.8),
+        rounding: 0.2,
+    });
+    let carved: Arc<dyn DistanceField> = Arc::new(SmoothSubtraction {
+        a: rounded_box,
+        b: Arc::new(SdfSphere {
+            center: point3::from(-2.5, 1.8, 0.),
+            radius: 0.6,
+        }),
+        k: 0.1,
+    });
+    objects.add(Arc::new(Sdf::from(
+        carved,
+        Aabb::from(&point3::from(-3.3, 0.2, -0.8), &point3::from(-1.7, 1.8, 0.8)),
+        Arc::new(Lambertian::from(color::from(0.8, 0.3, 0.1))),
+    )));
+
+    let blob: Arc<dyn DistanceField> = Arc::new(SmoothUnion {
+        a: Arc::new(SdfSphere {
+            center: point3::from(-0.3, 1., 0.),
+            radius: 0.6,
+        }),
+        b: Arc::new(SdfTorus {
+            center: point3::from(0.3, 1., 0.),
+            major_radius: 0.6,
+            minor_radius: 0.2,
+        }),
+        k: 0.3,
+    });
+    objects.add(Arc::new(Sdf::from(
+        blob,
+        Aabb::from(&point3::from(-1., 0.3, -1.), &point3::from(1.2, 1.7, 1.)),
+        Arc::new(Metal::from(color::from(0.7, 0.6, 0.5), 0.1)),
+    )));
+
+    objects.add(Arc::new(
+        Sdf::from(
+            Arc::new(Mandelbulb::from(point3::from(2.5, 1., 0.), 0.8)),
+            Aabb::from(&point3::from(1.5, 0., -1.), &point3::from(3.5, 2., 1.)),
+            Arc::new(Lambertian::from(color::from(0.4, 0.2, 0.6))),
+        )
+        .with_budget(1e-3, 512),
     ));
 
-    let ground_material = Arc::new(Lambertian::from_texture(checker));
-    world.add(Arc::new(Sphere::new(
-        point3::from(0., -1000., 0.),
-        1000.,
-        ground_material,
+    let difflight = Arc::new(DiffuseLight::from_color(color::from(4., 4., 4.)));
+    objects.add(Arc::new(XzRect::from(-3., 3., -2., 2., 6., difflight)));
+
+    objects
+}
+
+fn terrain() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let perlin = Perlin::new();
+    let ground = Arc::new(Lambertian::from(color::from(0.48, 0.83, 0.53)));
+    objects.add(Arc::new(Heightfield::from_fn(
+        256,
+        256,
+        point3::from(-1000., 0., -1000.),
+        Vec3::from(2000., 300., 2000.),
+        |u, v| perlin.turb(&point3::from(3. * u, 0., 3. * v), 4),
+        ground,
+    )));
+
+    let light = Arc::new(DiffuseLight::from_color(color(7.)));
+    objects.add(Arc::new(XzRect::from(-300., 300., -300., 300., 800., light)));
+
+    objects
+}
+
+fn furball() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::from(color::from(0.5, 0.5, 0.5)));
+    objects.add(Arc::new(Plane::from(
+        point3::new(),
+        Vec3::from(0., 1., 0.),
+        ground,
+    )));
+
+    let center = point3::from(0., 1., 0.);
+    let skin = Arc::new(Lambertian::from(color::from(0.3, 0.2, 0.1)));
+    objects.add(Arc::new(Sphere::new(center, 0.8, skin)));
+
+    let fur = Arc::new(KajiyaKay::from(
+        color::from(0.35, 0.18, 0.07),
+        color::from(0.1, 0.1, 0.1),
+        20.,
+    ));
+    let mut strands = HittableList::new();
+    for _ in 0..3000 {
+        let normal = Vec3::random_unit_vector();
+        let root = center + normal * 0.8;
+        let comb = Vec3::from(0., -0.15, 0.) + Vec3::random_in_unit_sphere() * 0.1;
+        strands.add(Arc::new(Curve::from(
+            [
+                root,
+                root + normal * 0.15,
+                root + normal * 0.3 + comb,
+                root + normal * 0.4 + comb * 2.,
+            ],
+            0.02,
+            0.002,
+            CurveType::Cylinder,
+            fur.clone(),
+        )));
+    }
+    objects.add(Arc::new(BvhNode::from(&mut strands, 0., 1.)));
+
+    objects
+}
+
+fn bumpy_spheres() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let noise = Arc::new(NoiseTexture::from(4.));
+    let ground = Arc::new(Bump::from(
+        Arc::new(Lambertian::from(color::from(0.5, 0.5, 0.5))),
+        noise.clone(),
+        0.05,
+    ));
+    objects.add(Arc::new(XzRect::from(-50., 50., -50., 50., 0., ground)));
+
+    let orange = Arc::new(Lambertian::from(color::from(0.8, 0.4, 0.1)));
+    objects.add(Arc::new(Sphere::new(
+        point3::from(-1.2, 1., 0.),
+        1.,
+        Arc::new(Bump::from(orange.clone(), noise.clone(), 0.1)),
+    )));
+
+    let rock = Mesh::uv_sphere(point3::from(1.2, 1., 0.), 0.9, 256, 128).displace(&*noise, 0.15);
+    objects.add(Arc::new(TriangleMesh::from(rock, orange)));
+
+    let light = Arc::new(DiffuseLight::from_color(color(4.)));
+    objects.add(Arc::new(XzRect::from(-3., 3., -2., 2., 6., light)));
+
+    objects
+}
+
+fn subdivided_shapes() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::from(color::from(0.5, 0.5, 0.5)));
+    objects.add(Arc::new(XzRect::from(-50., 50., -50., 50., 0., ground)));
+
+    let cube = |offset: Vec3| {
+        let positions = (0..8)
+            .map(|i| {
+                offset + point3::from((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32)
+            })
+            .collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        PolyMesh::from(positions, faces)
+    };
+    let red = Arc::new(Lambertian::from(color::from(0.7, 0.1, 0.1)));
+    objects.add(Arc::new(TriangleMesh::from(
+        cube(Vec3::from(-2.2, 0., 0.)).triangulate(),
+        red.clone(),
+    )));
+    objects.add(Arc::new(TriangleMesh::from(
+        cube(Vec3::from(-0.5, 0., 0.)).catmull_clark(4),
+        red.clone(),
+    )));
+
+    // Bottom face creased for good, top edges sharp for one level only
+    let creased = cube(Vec3::from(1.2, 0., 0.))
+        .with_crease(0, 1, f32::INFINITY)
+        .with_crease(1, 5, f32::INFINITY)
+        .with_crease(5, 4, f32::INFINITY)
+        .with_crease(4, 0, f32::INFINITY)
+        .with_crease(2, 3, 1.)
+        .with_crease(3, 7, 1.)
+        .with_crease(7, 6, 1.)
+        .with_crease(6, 2, 1.);
+    objects.add(Arc::new(TriangleMesh::from(creased.catmull_clark(4), red)));
+
+    let tetrahedron = PolyMesh::from(
+        vec![
+            point3::from(0., 0., 1.5),
+            point3::from(1., 0., 2.5),
+            point3::from(-1., 0., 2.5),
+            point3::from(0., 1.4, 2.),
+        ],
+        vec![vec![0, 2, 1], vec![0, 1, 3], vec![1, 2, 3], vec![2, 0, 3]],
+    );
+    objects.add(Arc::new(TriangleMesh::from(
+        tetrahedron.loop_subdivide(4),
+        Arc::new(Metal::from(color::from(0.8, 0.8, 0.9), 0.05)),
+    )));
+
+    let light = Arc::new(DiffuseLight::from_color(color(4.)));
+    objects.add(Arc::new(XzRect::from(-3., 3., -2., 2., 6., light)));
+
+    objects
+}
+
+fn bezier_patches() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::from(color::from(0.5, 0.5, 0.5)));
+    objects.add(Arc::new(XzRect::from(-50., 50., -50., 50., 0., ground)));
+
+    let mut cp = [point3::new(); 16];
+    for j in 0..4 {
+        for i in 0..4 {
+            let h = if (i + j) % 2 == 0 { 1.2 } else { 0.2 };
+            cp[4 * j + i] = point3::from(i as f32 - 4.5, h, j as f32 - 1.5);
+        }
+    }
+    objects.add(Arc::new(BezierPatch::from(
+        cp,
+        Arc::new(Metal::from(color::from(0.8, 0.6, 0.2), 0.1)),
+    )));
+
+    // The teapot file has z up, so it is stood up once tessellated
+    let teapot = BezierPatch::from_bpt_file("teapot.bpt", Arc::new(Lambertian::from(color(0.))));
+    if !teapot.is_empty() {
+        let mut mesh = Mesh::default();
+        for patch in &teapot {
+            mesh.append(&patch.tessellate(12));
+        }
+        let body = Arc::new(TriangleMesh::from(
+            mesh,
+            Arc::new(Lambertian::from(color::from(0.7, 0.7, 0.8))),
+        ));
+        objects.add(Arc::new(Instance::from(
+            body,
+            Vec3::from(0., 0., -1.),
+            -90.,
+            0.6,
+            Vec3::from(1.5, 0., 0.),
+        )));
+    }
+
+    let light = Arc::new(DiffuseLight::from_color(color(4.)));
+    objects.add(Arc::new(XzRect::from(-3., 3., -2., 2., 6., light)));
+
+    objects
+}
+
+fn blobs() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::from(color::from(0.5, 0.5, 0.5)));
+    objects.add(Arc::new(XzRect::from(-50., 50., -50., 50., 0., ground)));
+
+    let mut blobs = vec![];
+    for i in 0..12 {
+        let angle = i as f32 * 0.9;
+        blobs.push(Blob::from(
+            point3::from(angle.cos() * 1.2, 0.6 + 0.12 * i as f32, angle.sin() * 1.2),
+            0.9,
+            1.,
+        ));
+    }
+    // Carves a dent in the side facing the camera
+    blobs.push(Blob::from(point3::from(0., 1.2, 1.4), 0.6, -1.));
+    objects.add(Arc::new(Metaballs::from(
+        blobs,
+        0.3,
+        Arc::new(Lambertian::from(color::from(0.2, 0.5, 0.3))),
+    )));
+
+    let light = Arc::new(DiffuseLight::from_color(color(4.)));
+    objects.add(Arc::new(XzRect::from(-3., 3., -2., 2., 6., light)));
+
+    objects
+}
+
+fn rough_materials() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.2, 0.2, 0.2), color::from(0.8, 0.8, 0.8)));
+
+    // Roughness grows from left to right, metal in the back row and glass in the front
+    for i in 0..5 {
+        let roughness = i as f32 * 0.2;
+        let x = -4. + 2. * i as f32;
+        objects.add(Arc::new(Sphere::new(
+            point3::from(x, 0.8, -1.2),
+            0.8,
+            Arc::new(RoughConductor::from(color::from(0.95, 0.64, 0.54), roughness)),
+        )));
+        objects.add(Arc::new(Sphere::new(
+            point3::from(x, 0.8, 1.2),
+            0.8,
+            Arc::new(RoughDielectric::from(1.5, roughness)),
+        )));
+    }
+
+    objects
+}
+
+fn principled_spheres() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.2, 0.2, 0.2), color::from(0.8, 0.8, 0.8)));
+
+    let materials: Vec<Arc<dyn Material>> = vec![
+        // Plastic, brushed gold, car paint, velvet and frosted glass
+        Arc::new(Principled::from(color::from(0.1, 0.3, 0.8)).with_roughness(0.3)),
+        Arc::new(
+            Principled::from(color::from(1., 0.77, 0.34))
+                .with_metallic(1.)
+                .with_roughness(0.35),
+        ),
+        Arc::new(
+            Principled::from(color::from(0.6, 0.02, 0.02))
+                .with_roughness(0.6)
+                .with_clearcoat(1., 0.03),
+        ),
+        Arc::new(
+            Principled::from(color::from(0.25, 0.05, 0.3))
+                .with_roughness(1.)
+                .with_specular(0.)
+                .with_sheen(color::from(0.8, 0.6, 0.9)),
+        ),
+        Arc::new(
+            Principled::from(color::from(0.9, 1., 0.95))
+                .with_roughness(0.15)
+                .with_transmission(1.),
+        ),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            point3::from(-4. + 2. * i as f32, 0.8, 0.),
+            0.8,
+            mat,
+        )));
+    }
+
+    objects
+}
+
+fn metals() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.2, 0.2, 0.2), color::from(0.8, 0.8, 0.8)));
+
+    for (i, name) in ["Au", "Ag", "Cu", "Al", "Cr", "Ti"].iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            point3::from(-5. + 2. * i as f32, 0.8, 0.),
+            0.8,
+            Arc::new(RoughConductor::from_metal(name, 0.15)),
+        )));
+    }
+
+    objects
+}
+
+fn colored_glass() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.2, 0.2, 0.2), color::from(0.8, 0.8, 0.8)));
+
+    // The same glass deepens in color with thickness
+    let sigma = color::from(0.1, 0.6, 1.2);
+    let mut x = -4.7;
+    for &radius in &[0.3, 0.6, 1., 1.4] {
+        objects.add(Arc::new(Sphere::new(
+            point3::from(x + radius, radius, 0.),
+            radius,
+            Arc::new(Dielectric::new(1.5).with_absorption(sigma)),
+        )));
+        x += 2. * radius + 0.3;
+    }
+    objects.add(Arc::new(Sphere::new(
+        point3::from(x + 0.8, 0.8, 0.),
+        0.8,
+        Arc::new(RoughDielectric::from(1.5, 0.3).with_absorption(sigma)),
     )));
 
+    objects
+}
+
+fn dispersion() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.05, 0.05, 0.05), color::from(0.9, 0.9, 0.9)));
+
+    objects.add(Arc::new(Sphere::new(
+        point3::from(-1.5, 1.2, 0.),
+        1.2,
+        Arc::new(Dielectric::dispersive(BK7)),
+    )));
+    // Flint glass with its dispersion exaggerated fourfold
+    objects.add(Arc::new(Sphere::new(
+        point3::from(1.5, 1.2, 0.),
+        1.2,
+        Arc::new(Dielectric::dispersive(Dispersion::Cauchy { a: 1.67, b: 0.03 })),
+    )));
+
+    objects
+}
+
+fn iridescence() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.05, 0.05, 0.05), color::from(0.9, 0.9, 0.9)));
+
+    // Soap bubble, anodized titanium and a lens with an anti-reflective coating
+    objects.add(Arc::new(Sphere::new(
+        point3::from(-2.6, 1.2, 0.),
+        1.2,
+        Arc::new(Dielectric::new(1.).with_thin_film(ThinFilm::from(380., 1.33))),
+    )));
+    objects.add(Arc::new(Sphere::new(
+        point3::from(0., 1.2, 0.),
+        1.2,
+        Arc::new(
+            RoughConductor::from_metal("Ti", 0.1).with_thin_film(ThinFilm::from(120., 2.4)),
+        ),
+    )));
+    objects.add(Arc::new(Sphere::new(
+        point3::from(2.6, 1.2, 0.),
+        1.2,
+        Arc::new(Dielectric::new(1.5).with_thin_film(ThinFilm::from(100., 1.38))),
+    )));
+
+    objects
+}
+
+fn coated_spheres() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.2, 0.2, 0.2), color::from(0.8, 0.8, 0.8)));
+
+    // Car paint, metallic paint and varnished wood
+    let paint = Arc::new(Lambertian::from(color::from(0.7, 0.05, 0.05)));
+    let flakes = Arc::new(RoughConductor::from_metal("Cu", 0.4));
+    let wood = Arc::new(Lambertian::from_texture(Arc::new(NoiseTexture::from(4.))));
+    let materials: Vec<Arc<dyn Material>> = vec![
+        Arc::new(Coated::from(paint, 1.5)),
+        Arc::new(Coated::from(flakes, 1.5).with_roughness(0.05)),
+        Arc::new(
+            Coated::from(wood, 1.5)
+                .with_roughness(0.15)
+                .with_absorption(color::from(0.5, 1.5, 4.), 0.3),
+        ),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            point3::from(-2.6 + 2.6 * i as f32, 1.2, 0.),
+            1.2,
+            mat,
+        )));
+    }
+
+    objects
+}
+
+fn subsurface_spheres() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.2, 0.2, 0.2), color::from(0.8, 0.8, 0.8)));
+
+    // Wax, skin, marble and milk
+    let media = [
+        (color::from(0.9, 0.8, 0.5), color::from(0.4, 0.3, 0.15)),
+        (color::from(0.85, 0.6, 0.5), color::from(0.37, 0.14, 0.08)),
+        (color::from(0.9, 0.9, 0.88), color::from(0.25, 0.25, 0.3)),
+        (color::from(0.95, 0.95, 0.9), color::from(0.1, 0.1, 0.12)),
+    ];
+    for (i, &(albedo, mean_free_path)) in media.iter().enumerate() {
+        let center = point3::from(-3.3 + 2.2 * i as f32, 1., 0.);
+        let boundary = Arc::new(Sphere::new(
+            center,
+            1.,
+            Arc::new(Lambertian::from(color::new())),
+        ));
+        objects.add(Arc::new(Subsurface::from(
+            boundary,
+            albedo,
+            mean_free_path,
+            1.3,
+        )));
+    }
+
+    objects
+}
+
+fn mixed_materials() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.2, 0.2, 0.2), color::from(0.8, 0.8, 0.8)));
+
+    // Rust patches over polished steel, and copper going green
+    let steel = Arc::new(Metal::from(color::from(0.8, 0.8, 0.85), 0.05));
+    let rust = Arc::new(Lambertian::from(color::from(0.45, 0.2, 0.08)));
+    objects.add(Arc::new(Sphere::new(
+        point3::from(-1.3, 1., -0.5),
+        1.,
+        Arc::new(MixMaterial::from_texture(
+            steel,
+            rust,
+            Arc::new(NoiseTexture::from(4.)),
+        )),
+    )));
+    let copper = Arc::new(RoughConductor::from_metal("Cu", 0.2));
+    let patina = Arc::new(Lambertian::from(color::from(0.3, 0.6, 0.5)));
+    objects.add(Arc::new(Sphere::new(
+        point3::from(1.3, 1., -0.5),
+        1.,
+        Arc::new(MixMaterial::from(copper, patina, 0.4)),
+    )));
+
+    // A fence in front, its holes cut out of a single quad
+    let holes = Arc::new(CheckerTexture::from_colors(
+        color::from(1., 1., 1.),
+        color::new(),
+    ));
+    let wood = Arc::new(Lambertian::from(color::from(0.5, 0.35, 0.2)));
+    objects.add(Arc::new(XyRect::from(
+        -3.,
+        3.,
+        0.,
+        0.8,
+        1.5,
+        Arc::new(Cutout::from(wood, holes)),
+    )));
+
+    objects
+}
+
+fn diffuse_models() -> HittableList {
+    let mut objects = HittableList::new();
+
+    objects.add(checker_ground(color::from(0.2, 0.2, 0.2), color::from(0.8, 0.8, 0.8)));
+
+    // Lambertian and Oren–Nayar clay, a road-sign coating and a paper lampshade
+    let clay = color::from(0.7, 0.45, 0.3);
+    let materials: Vec<Arc<dyn Material>> = vec![
+        Arc::new(Lambertian::from(clay)),
+        Arc::new(OrenNayar::from(clay, 1.)),
+        Arc::new(Retroreflective::from(color::from(0.9, 0.8, 0.1), 30.).with_diffuse(0.2)),
+        Arc::new(TranslucentDiffuse::from(
+            color::from(0.6, 0.55, 0.5),
+            color::from(0.3, 0.27, 0.2),
+        )),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        objects.add(Arc::new(Sphere::new(
+            point3::from(-3.3 + 2.2 * i as f32, 1., 0.),
+            1.,
+            mat,
+        )));
+    }
+    objects.add(Arc::new(Sphere::new(
+        point3::from(3.3, 1., 0.),
+        0.3,
+        Arc::new(DiffuseLight::from_color(color::from(15., 13., 10.))),
+    )));
+
+    objects
+}
+
+fn random_scene() -> HittableList {
+    let mut world = HittableList::new();
+
+    world.add(checker_ground(color::from(0.2, 0.3, 0.1), color::from(0.9, 0.9, 0.9)));
+
     for a in -11..11 {
         for b in -11..11 {
             let choose_mat = random_double(0., 1.);
@@ -459,9 +1100,14 @@ fn main() {
     let mut aperture: f32 = 0.0;
     let mut vfov = 40.;
     let mut background = color::new();
+    // Traces sampled wavelengths instead of RGB, needed for dispersion
+    let mut spectral = false;
 
     let mode = 8;
 
+    // Checks every hit against the bounding box of the object hit, slow but catches broken boxes
+    set_bounds_validation(std::env::var_os("RAYTRACER_VALIDATE_BOUNDS").is_some());
+
     match mode {
         0 => {
             world = two_spheres();
@@ -528,6 +1174,135 @@ fn main() {
             lookat = point3::from(278., 278., 0.);
             vfov = 40.;
         }
+        9 => {
+            world = sdf_shapes();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 3., 9.);
+            lookat = point3::from(0., 1., 0.);
+            vfov = 35.;
+        }
+        10 => {
+            world = terrain();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 500., -1200.);
+            lookat = point3::from(0., 0., 0.);
+            vfov = 40.;
+        }
+        11 => {
+            world = cornell_clouds();
+            aspect_ratio = 1.;
+            image_width = 600;
+            samples_per_pixel = 200;
+            lookfrom = point3::from(278., 278., -800.);
+            lookat = point3::from(278., 278., 0.);
+            vfov = 40.;
+        }
+        12 => {
+            world = furball();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 2., 6.);
+            lookat = point3::from(0., 1., 0.);
+            vfov = 30.;
+        }
+        13 => {
+            world = bumpy_spheres();
+            background = color::from(0.1, 0.1, 0.15);
+            lookfrom = point3::from(0., 3., 8.);
+            lookat = point3::from(0., 1., 0.);
+            vfov = 30.;
+        }
+        14 => {
+            world = subdivided_shapes();
+            background = color::from(0.1, 0.1, 0.15);
+            lookfrom = point3::from(0., 4., 9.);
+            lookat = point3::from(0., 0.7, 1.);
+            vfov = 30.;
+        }
+        15 => {
+            world = bezier_patches();
+            background = color::from(0.1, 0.1, 0.15);
+            lookfrom = point3::from(0., 4., 9.);
+            lookat = point3::from(-1., 0.7, 0.);
+            vfov = 35.;
+        }
+        16 => {
+            world = blobs();
+            background = color::from(0.1, 0.1, 0.15);
+            lookfrom = point3::from(0., 4., 9.);
+            lookat = point3::from(0., 1.2, 0.);
+            vfov = 30.;
+        }
+        17 => {
+            world = rough_materials();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 5., 12.);
+            lookat = point3::from(0., 0.6, 0.);
+            vfov = 35.;
+        }
+        18 => {
+            world = principled_spheres();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 3., 12.);
+            lookat = point3::from(0., 0.8, 0.);
+            vfov = 30.;
+        }
+        19 => {
+            world = metals();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 3., 14.);
+            lookat = point3::from(0., 0.8, 0.);
+            vfov = 30.;
+        }
+        20 => {
+            world = colored_glass();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 3., 14.);
+            lookat = point3::from(0., 0.8, 0.);
+            vfov = 30.;
+        }
+        21 => {
+            world = dispersion();
+            spectral = true;
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 2.5, 9.);
+            lookat = point3::from(0., 1., 0.);
+            vfov = 35.;
+        }
+        22 => {
+            world = iridescence();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 2.5, 11.);
+            lookat = point3::from(0., 1., 0.);
+            vfov = 35.;
+        }
+        23 => {
+            world = coated_spheres();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 2.5, 11.);
+            lookat = point3::from(0., 1., 0.);
+            vfov = 35.;
+        }
+        24 => {
+            world = subsurface_spheres();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 2.5, 11.);
+            lookat = point3::from(0., 1., 0.);
+            vfov = 35.;
+        }
+        25 => {
+            world = mixed_materials();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 2., 9.);
+            lookat = point3::from(0., 0.8, 0.);
+            vfov = 35.;
+        }
+        26 => {
+            world = diffuse_models();
+            background = color::from(0.7, 0.8, 1.);
+            lookfrom = point3::from(0., 2.5, 11.);
+            lookat = point3::from(0., 1., 0.);
+            vfov = 35.;
+        }
         _ => {
             world = random_scene();
         }
@@ -553,7 +1328,14 @@ fn main() {
             let u = (i as f32 + random_double(0., 1.)) / ((image_width - 1) as f32);
             let v = (j as f32 + random_double(0., 1.)) / ((image_height - 1) as f32);
             let r = cam.get_ray(u, v);
-            pixel_color.add(ray_color(r, &background, &world, MAX_DEPTH as i32));
+            if spectral {
+                let mut wavelengths = SampledWavelengths::sample();
+                let radiance =
+                    ray_spectrum(r, &background, &world, MAX_DEPTH as i32, &mut wavelengths);
+                pixel_color.add(wavelengths.to_xyz(&radiance));
+            } else {
+                pixel_color.add(ray_color(r, &background, &world, MAX_DEPTH as i32));
+            }
         }
         pixel_color
     };
@@ -575,6 +1357,7 @@ fn main() {
             );
         },
     );
+    let c = if spectral { c.xyz_to_srgb() } else { c };
     c.write_header();
     c.write_pixels();
     let elapsed = time.elapsed();
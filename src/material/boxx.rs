@@ -1,6 +1,6 @@
 use crate::{
-  aarect::*, material::material::*, objects::hittable::*, objects::hittable_list::*, ray::*,
-  vec3::*,
+  aabb::*, aarect::*, material::material::*, objects::hittable::*, objects::hittable_list::*,
+  ray::*, vec3::*,
 };
 use std::sync::Arc;
 
@@ -87,7 +87,10 @@ impl Boxx {
 }
 
 impl Hittable for Boxx {
-  fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
-    self.sides.hit(r, t_min, t_max, rec)
+  fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+    self.sides.hit(r, t_min, t_max)
+  }
+  fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+    Some(Aabb::from(&self.box_min, &self.box_max))
   }
 }
@@ -0,0 +1,214 @@
+use crate::{material::material::*, objects::hittable::HitRecord, ray::*, texture::*, vec3::*};
+use std::sync::Arc;
+
+/// Perturbs the shading normal as if the surface was displaced along its normal by `scale`
+/// times the average channel of `height`, then lets `base` scatter
+pub struct Bump {
+    base: Arc<dyn Material>,
+    height: Arc<dyn Texture>,
+    scale: f32,
+}
+
+impl Bump {
+    pub fn from(base: Arc<dyn Material>, height: Arc<dyn Texture>, scale: f32) -> Self {
+        Self {
+            base,
+            height,
+            scale,
+        }
+    }
+    fn displacement(&self, u: f32, v: f32, p: &point3) -> f32 {
+        let h = self.height.value(u, v, p);
+        self.scale * (h.x() + h.y() + h.z()) / 3.
+    }
+}
+
+/// World space distance over which the height is differentiated
+const BUMP_STEP: f32 = 0.0005;
+
+impl Material for Bump {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            return self.base.scatter(r_in, rec);
+        }
+        let outward = if rec.front_face {
+            rec.normal
+        } else {
+            rec.normal.inv()
+        };
+
+        let du = BUMP_STEP / rec.dpdu.len();
+        let dv = BUMP_STEP / rec.dpdv.len();
+        let h = self.displacement(rec.u, rec.v, &rec.p);
+        let h_u = self.displacement(rec.u + du, rec.v, &(rec.p + rec.dpdu * du));
+        let h_v = self.displacement(rec.u, rec.v + dv, &(rec.p + rec.dpdv * dv));
+
+        let dpdu = rec.dpdu + outward * ((h_u - h) / du);
+        let dpdv = rec.dpdv + outward * ((h_v - h) / dv);
+        let mut bumped = unit_vector(cross(dpdu, dpdv));
+        if dot(bumped, rec.normal) < 0. {
+            bumped = bumped.inv();
+        }
+
+        self.base.scatter(r_in, &shade_with(rec, r_in, bumped))
+    }
+    fn emitted(&self, u: f32, v: f32, p: &point3) -> color {
+        self.base.emitted(u, v, p)
+    }
+    fn absorption(&self) -> color {
+        self.base.absorption()
+    }
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
+
+/// Replaces the shading normal with one read from a tangent-space normal map, whose red and
+/// green channels follow `dpdu` and `dpdv` and blue the surface normal
+pub struct NormalMap {
+    base: Arc<dyn Material>,
+    map: Arc<dyn Texture>,
+    strength: f32,
+}
+
+impl NormalMap {
+    pub fn from(base: Arc<dyn Material>, map: Arc<dyn Texture>) -> Self {
+        Self {
+            base,
+            map,
+            strength: 1.,
+        }
+    }
+    /// Scales the tilt read from the map, 0 flattening it out
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+            return self.base.scatter(r_in, rec);
+        }
+        let outward = if rec.front_face {
+            rec.normal
+        } else {
+            rec.normal.inv()
+        };
+        let tangent = unit_vector(rec.dpdu - outward * dot(rec.dpdu, outward));
+        let bitangent = rec.dpdv - outward * dot(rec.dpdv, outward);
+        let bitangent = unit_vector(bitangent - tangent * dot(bitangent, tangent));
+
+        let c = self.map.value(rec.u, rec.v, &rec.p);
+        let local = Vec3::from(
+            (2. * c.x() - 1.) * self.strength,
+            (2. * c.y() - 1.) * self.strength,
+            (2. * c.z() - 1.).max(0.),
+        );
+        if local.near_zero() {
+            return self.base.scatter(r_in, rec);
+        }
+        let mut mapped =
+            unit_vector(tangent * local.x() + bitangent * local.y() + outward * local.z());
+        if !rec.front_face {
+            mapped = mapped.inv();
+        }
+
+        self.base.scatter(r_in, &shade_with(rec, r_in, mapped))
+    }
+    fn emitted(&self, u: f32, v: f32, p: &point3) -> color {
+        self.base.emitted(u, v, p)
+    }
+    fn absorption(&self) -> color {
+        self.base.absorption()
+    }
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
+
+/// Copy of `rec` shaded with `normal`, unless that normal faces away from the viewer
+fn shade_with<'a>(rec: &HitRecord<'a>, r_in: &Ray, normal: Vec3) -> HitRecord<'a> {
+    let mut shading = *rec;
+    if dot(normal, r_in.direction()) < 0. {
+        shading.normal = normal;
+    }
+    shading
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{aarect::XzRect, objects::hittable::Hittable};
+
+    /// Scatters along the shading normal it is given
+    struct NormalProbe;
+
+    impl Material for NormalProbe {
+        fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+            Some(ScatterRecord::specular(
+                color::new(),
+                Ray::new(rec.p, rec.normal, r_in.time()),
+            ))
+        }
+    }
+
+    /// Height rising along x
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, _u: f32, _v: f32, p: &point3) -> color {
+            color::from(p.x(), p.x(), p.x())
+        }
+    }
+
+    fn shading_normal(mat: Arc<dyn Material>) -> Vec3 {
+        let rect = XzRect::from(-1., 1., -1., 1., 0., mat);
+        let r = Ray::new(point3::from(0.2, 1., 0.3), Vec3::from(0., -1., 0.), 0.);
+        let rec = rect.hit(&r, 0.001, f32::INFINITY).unwrap();
+        rec.mat_ptr.scatter(&r, &rec).unwrap().scattered.direction()
+    }
+
+    #[test]
+    fn bump_and_normal_maps_tilt_the_normal() {
+        let probe: Arc<dyn Material> = Arc::new(NormalProbe);
+        let up = Vec3::from(0., 1., 0.);
+
+        let flat = Bump::from(
+            probe.clone(),
+            Arc::new(SolidColor::from(color::from(1., 1., 1.))),
+            1.,
+        );
+        assert!((shading_normal(Arc::new(flat)) - up).len() < 1e-4);
+        // Height 0.5 x: the surface leans back by a slope of 0.5
+        let ramp = Bump::from(probe.clone(), Arc::new(Ramp), 0.5);
+        let expected = unit_vector(Vec3::from(-0.5, 1., 0.));
+        assert!((shading_normal(Arc::new(ramp)) - expected).len() < 1e-3);
+
+        let neutral = NormalMap::from(
+            probe.clone(),
+            Arc::new(SolidColor::from(color::from(0.5, 0.5, 1.))),
+        );
+        assert!((shading_normal(Arc::new(neutral)) - up).len() < 1e-4);
+        // Red tilts the normal towards dP/du, here +x
+        let tilted = NormalMap::from(probe, Arc::new(SolidColor::from(color::from(1., 0.5, 1.))));
+        let expected = unit_vector(Vec3::from(1., 1., 0.));
+        assert!((shading_normal(Arc::new(tilted)) - expected).len() < 1e-4);
+    }
+
+    #[test]
+    fn maps_keep_the_base_glass_properties() {
+        let sigma = color::from(0.1, 0.2, 0.3);
+        let glass: Arc<dyn Material> = Arc::new(Dielectric::dispersive(BK7).with_absorption(sigma));
+        let height = Arc::new(SolidColor::from(color::from(0.5, 0.5, 1.)));
+        let maps: Vec<Box<dyn Material>> = vec![
+            Box::new(Bump::from(glass.clone(), height.clone(), 0.1)),
+            Box::new(NormalMap::from(glass, height)),
+        ];
+        for map in &maps {
+            assert!(map.is_dispersive());
+            assert!((map.absorption() - sigma).len() < 1e-6);
+        }
+    }
+}
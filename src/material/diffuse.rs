@@ -0,0 +1,293 @@
+use crate::{
+    material::material::*, objects::hittable::HitRecord, onb::*, ray::*, texture::*, utils::*,
+    vec3::*,
+};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Scattering function with an explicit value and density, expressed in the shading frame of a
+/// hit where the normal is +z. `wo` points back along the incoming ray and `wi` towards where
+/// the light comes from, both unit length
+pub trait Bsdf: Send + Sync {
+    /// Value of the BSDF, without the cosine of `wi`
+    fn f(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> color;
+    /// Samples `wi` for `wo`, `None` when the sample is lost
+    fn sample(&self, rec: &HitRecord, wo: Vec3) -> Option<Vec3>;
+    /// Probability density of `sample` returning `wi`, per solid angle
+    fn pdf(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> f32;
+}
+
+/// `Material::scatter` for any `Bsdf`, weighting the sample by f cosθ / pdf
+pub fn scatter_bsdf(bsdf: &dyn Bsdf, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    let frame = Onb::build_from_w(rec.normal);
+    let wo = frame.to_local(unit_vector(r_in.direction()).inv());
+    if wo.z() <= 0. {
+        return None;
+    }
+    let wi = bsdf.sample(rec, wo)?;
+    let pdf = bsdf.pdf(rec, wo, wi);
+    if pdf <= 0. {
+        return None;
+    }
+    Some(ScatterRecord::diffuse(
+        bsdf.f(rec, wo, wi) * (wi.z().abs() / pdf),
+        Ray::new(rec.p, frame.local_vec(wi), r_in.time()),
+    ))
+}
+
+/// Cosine-weighted direction on the +z hemisphere
+fn sample_cosine_hemisphere() -> Vec3 {
+    let r = random_double(0., 1.).sqrt();
+    let phi = 2. * PI * random_double(0., 1.);
+    let z = (1. - r * r).max(0.).sqrt();
+    Vec3::from(r * phi.cos(), r * phi.sin(), z)
+}
+
+impl Bsdf for Lambertian {
+    fn f(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> color {
+        if wo.z() * wi.z() <= 0. {
+            return color::new();
+        }
+        self.albedo.value(rec.u, rec.v, &rec.p) / PI
+    }
+    fn sample(&self, _rec: &HitRecord, _wo: Vec3) -> Option<Vec3> {
+        Some(sample_cosine_hemisphere())
+    }
+    fn pdf(&self, _rec: &HitRecord, _wo: Vec3, wi: Vec3) -> f32 {
+        wi.z().max(0.) / PI
+    }
+}
+
+/// Rough diffuse surface such as clay, plaster or the moon, made of V-shaped Lambertian
+/// facets. It looks flatter than `Lambertian` and brighter towards the light
+pub struct OrenNayar {
+    albedo: Arc<dyn Texture>,
+    a: f32,
+    b: f32,
+}
+
+impl OrenNayar {
+    /// `roughness` is the standard deviation of the facet slopes in radians, 0 being Lambertian
+    pub fn from(albedo: color, roughness: f32) -> Self {
+        Self::from_texture(Arc::new(SolidColor::from(albedo)), roughness)
+    }
+    pub fn from_texture(albedo: Arc<dyn Texture>, roughness: f32) -> Self {
+        let s2 = roughness * roughness;
+        Self {
+            albedo,
+            a: 1. - 0.5 * s2 / (s2 + 0.33),
+            b: 0.45 * s2 / (s2 + 0.09),
+        }
+    }
+}
+
+impl Bsdf for OrenNayar {
+    fn f(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> color {
+        if wo.z() <= 0. || wi.z() <= 0. {
+            return color::new();
+        }
+        let sin_i = (1. - wi.z() * wi.z()).max(0.).sqrt();
+        let sin_o = (1. - wo.z() * wo.z()).max(0.).sqrt();
+        let cos_phi = if sin_i > 1e-4 && sin_o > 1e-4 {
+            ((wi.x() * wo.x() + wi.y() * wo.y()) / (sin_i * sin_o)).max(0.)
+        } else {
+            0.
+        };
+        // α is the larger of the two polar angles and β the smaller
+        let (sin_alpha, tan_beta) = if wi.z() > wo.z() {
+            (sin_o, sin_i / wi.z())
+        } else {
+            (sin_i, sin_o / wo.z())
+        };
+        self.albedo.value(rec.u, rec.v, &rec.p)
+            * ((self.a + self.b * cos_phi * sin_alpha * tan_beta) / PI)
+    }
+    fn sample(&self, _rec: &HitRecord, _wo: Vec3) -> Option<Vec3> {
+        Some(sample_cosine_hemisphere())
+    }
+    fn pdf(&self, _rec: &HitRecord, _wo: Vec3, wi: Vec3) -> f32 {
+        wi.z().max(0.) / PI
+    }
+}
+
+impl Material for OrenNayar {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        scatter_bsdf(self, r_in, rec)
+    }
+}
+
+/// Backscattering surface like road signs or high-visibility tape, sending light back towards
+/// where it came from in a Phong lobe of `exponent` around the incoming ray
+pub struct Retroreflective {
+    albedo: Arc<dyn Texture>,
+    exponent: f32,
+    diffuse: f32,
+}
+
+impl Retroreflective {
+    pub fn from(albedo: color, exponent: f32) -> Self {
+        Self::from_texture(Arc::new(SolidColor::from(albedo)), exponent)
+    }
+    pub fn from_texture(albedo: Arc<dyn Texture>, exponent: f32) -> Self {
+        Self {
+            albedo,
+            exponent: exponent.max(0.),
+            diffuse: 0.,
+        }
+    }
+    /// Fraction of the light scattered diffusely instead of back
+    pub fn with_diffuse(mut self, diffuse: f32) -> Self {
+        self.diffuse = clamp(diffuse, 0., 1.);
+        self
+    }
+    /// Density of the retroreflected lobe around `wo`
+    fn lobe(&self, wo: Vec3, wi: Vec3) -> f32 {
+        (self.exponent + 1.) / (2. * PI) * dot(wo, wi).max(0.).powf(self.exponent)
+    }
+}
+
+impl Bsdf for Retroreflective {
+    fn f(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> color {
+        if wo.z() <= 0. || wi.z() <= 0. {
+            return color::new();
+        }
+        // The lobe is divided by the cosine so the reflected energy never exceeds the albedo
+        let retro = (1. - self.diffuse) * self.lobe(wo, wi) / wi.z();
+        self.albedo.value(rec.u, rec.v, &rec.p) * (self.diffuse / PI + retro)
+    }
+    fn sample(&self, _rec: &HitRecord, wo: Vec3) -> Option<Vec3> {
+        if self.diffuse > random_double(0., 1.) {
+            return Some(sample_cosine_hemisphere());
+        }
+        let cos_theta = random_double(0., 1.).powf(1. / (self.exponent + 1.));
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * PI * random_double(0., 1.);
+        let wi =
+            Onb::build_from_w(wo).local(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+        // Part of the lobe of grazing rays falls below the surface
+        if wi.z() <= 0. {
+            return None;
+        }
+        Some(wi)
+    }
+    fn pdf(&self, _rec: &HitRecord, wo: Vec3, wi: Vec3) -> f32 {
+        if wi.z() <= 0. {
+            return 0.;
+        }
+        self.diffuse * wi.z() / PI + (1. - self.diffuse) * self.lobe(wo, wi)
+    }
+}
+
+impl Material for Retroreflective {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        scatter_bsdf(self, r_in, rec)
+    }
+}
+
+/// Thin diffuser like paper or a lampshade, scattering `reflectance` back diffusely and
+/// `transmittance` diffusely through to the other side
+pub struct TranslucentDiffuse {
+    reflectance: Arc<dyn Texture>,
+    transmittance: Arc<dyn Texture>,
+}
+
+impl TranslucentDiffuse {
+    pub fn from(reflectance: color, transmittance: color) -> Self {
+        Self::from_texture(
+            Arc::new(SolidColor::from(reflectance)),
+            Arc::new(SolidColor::from(transmittance)),
+        )
+    }
+    pub fn from_texture(reflectance: Arc<dyn Texture>, transmittance: Arc<dyn Texture>) -> Self {
+        Self {
+            reflectance,
+            transmittance,
+        }
+    }
+    /// Probability of sampling the reflected side, by the energy each side carries
+    fn reflect_probability(&self, rec: &HitRecord) -> f32 {
+        let mean = |c: color| (c.x() + c.y() + c.z()) / 3.;
+        let r = mean(self.reflectance.value(rec.u, rec.v, &rec.p));
+        let t = mean(self.transmittance.value(rec.u, rec.v, &rec.p));
+        if r + t <= 0. {
+            0.5
+        } else {
+            r / (r + t)
+        }
+    }
+}
+
+impl Bsdf for TranslucentDiffuse {
+    fn f(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> color {
+        if wo.z() * wi.z() > 0. {
+            self.reflectance.value(rec.u, rec.v, &rec.p) / PI
+        } else {
+            self.transmittance.value(rec.u, rec.v, &rec.p) / PI
+        }
+    }
+    fn sample(&self, rec: &HitRecord, _wo: Vec3) -> Option<Vec3> {
+        let wi = sample_cosine_hemisphere();
+        if self.reflect_probability(rec) > random_double(0., 1.) {
+            Some(wi)
+        } else {
+            Some(Vec3::from(wi.x(), wi.y(), -wi.z()))
+        }
+    }
+    fn pdf(&self, rec: &HitRecord, wo: Vec3, wi: Vec3) -> f32 {
+        let p = self.reflect_probability(rec);
+        let side = if wo.z() * wi.z() > 0. { p } else { 1. - p };
+        side * wi.z().abs() / PI
+    }
+}
+
+impl Material for TranslucentDiffuse {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        scatter_bsdf(self, r_in, rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn densities_integrate_to_one_and_match_the_weights() {
+        let r = Ray::new(point3::from(-0.3, 1., 0.), Vec3::from(0.3, -1., 0.), 0.);
+        let wo = unit_vector(Vec3::from(-0.3, 0., 1.));
+        let white = color::from(1., 1., 1.);
+        let models: Vec<Box<dyn Bsdf>> = vec![
+            Box::new(Lambertian::from(white)),
+            Box::new(OrenNayar::from(white, 0.5)),
+            Box::new(Retroreflective::from(white, 20.).with_diffuse(0.3)),
+            Box::new(TranslucentDiffuse::from(white * 0.3, white * 0.6)),
+        ];
+        let dummy = Lambertian::from(white);
+        let rec = HitRecord::from(&r, 1., Vec3::from(0., 0., 1.), (0., 0.), &dummy);
+        for bsdf in &models {
+            // Uniform directions over the sphere, of density 1 / 4π
+            let n = 100_000;
+            let total = (0..n).fold(0., |acc, _| {
+                acc + bsdf.pdf(&rec, wo, Vec3::random_unit_vector()) * 4. * PI
+            });
+            let integral = total / n as f32;
+            assert!((integral - 1.).abs() < 0.05, "{}", integral);
+
+            // A white surface reflects at most what it receives
+            let mut energy = 0.;
+            for _ in 0..n {
+                if let Some(wi) = bsdf.sample(&rec, wo) {
+                    let pdf = bsdf.pdf(&rec, wo, wi);
+                    assert!(pdf > 0.);
+                    energy += bsdf.f(&rec, wo, wi).x() * wi.z().abs() / pdf;
+                }
+            }
+            let energy = energy / n as f32;
+            assert!(energy > 0.8 && energy < 1.01, "{}", energy);
+        }
+
+        // Smooth Oren–Nayar is Lambertian
+        let smooth = OrenNayar::from(white, 0.);
+        let wi = unit_vector(Vec3::from(0.5, 0.2, 0.6));
+        assert!((smooth.f(&rec, wo, wi).x() - 1. / PI).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{material::material::*, objects::hittable::HitRecord, ray::*, texture::*, vec3::*};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Kajiya–Kay hair shading: a diffuse term following the strand cross-section and a specular
+/// cone around the mirror direction along the strand. Needs the tangent of `HitRecord::dpdu`
+pub struct KajiyaKay {
+    diffuse: Arc<dyn Texture>,
+    specular: color,
+    exponent: f32,
+}
+
+impl KajiyaKay {
+    pub fn from(diffuse: color, specular: color, exponent: f32) -> Self {
+        Self::from_texture(Arc::new(SolidColor::from(diffuse)), specular, exponent)
+    }
+    pub fn from_texture(diffuse: Arc<dyn Texture>, specular: color, exponent: f32) -> Self {
+        Self {
+            diffuse,
+            specular,
+            exponent,
+        }
+    }
+    /// Strand response to light travelling along `wi` and leaving along `wo`, both unit length
+    fn eval(&self, rec: &HitRecord, tangent: Vec3, wi: Vec3, wo: Vec3) -> color {
+        let cos_i = dot(tangent, wi);
+        let cos_o = dot(tangent, wo);
+        let sin_i = (1. - cos_i * cos_i).max(0.).sqrt();
+        let sin_o = (1. - cos_o * cos_o).max(0.).sqrt();
+
+        // Normalized so the diffuse lobe integrates to its albedo over the sphere
+        let diffuse = self.diffuse.value(rec.u, rec.v, &rec.p) * (sin_o / (PI * PI));
+        let cone = (cos_i * cos_o + sin_i * sin_o).max(0.);
+        let specular =
+            self.specular * ((self.exponent + 2.) / (2. * PI) * cone.powf(self.exponent));
+        diffuse + specular
+    }
+}
+
+impl Material for KajiyaKay {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if rec.dpdu.near_zero() {
+            return None;
+        }
+        let tangent = unit_vector(rec.dpdu);
+        let wi = unit_vector(r_in.direction());
+
+        // Strands scatter all around, so directions are drawn over the whole sphere
+        let wo = Vec3::random_unit_vector();
+        let pdf = 1. / (4. * PI);
+
+        Some(ScatterRecord::diffuse(
+            self.eval(rec, tangent, wi, wo) / pdf,
+            Ray::new(rec.p, wo, r_in.time()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffuse_strand_keeps_its_albedo() {
+        let hair = KajiyaKay::from(color::from(0.8, 0.5, 0.2), color::new(), 20.);
+        let r = Ray::new(point3::from(0., 1., 1.), Vec3::from(0., -1., -1.), 0.);
+        let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), &hair)
+            .with_tangents((Vec3::from(1., 0., 0.), Vec3::from(0., 0., 1.)));
+
+        let n = 50_000;
+        let mut total = color::new();
+        for _ in 0..n {
+            let srec = hair.scatter(&r, &rec).unwrap();
+            assert!((srec.scattered.direction().len() - 1.).abs() < 1e-4);
+            total = total + srec.attenuation;
+        }
+        let mean = total / n as f32;
+        assert!((mean.x() - 0.8).abs() < 0.02, "{:?}", mean);
+        assert!((mean.z() - 0.2).abs() < 0.02, "{:?}", mean);
+    }
+}
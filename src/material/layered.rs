@@ -0,0 +1,226 @@
+use crate::{
+    material::material::*, material::microfacet::*, objects::hittable::HitRecord, onb::*, ray::*,
+    texture::*, vec3::*,
+};
+use std::sync::Arc;
+
+/// Crossings of the coating after which a path still trapped between the layers is dropped
+const MAX_BOUNCES: usize = 32;
+
+/// Dielectric coating over any base material, like the clearcoat of car paint or varnish over
+/// wood. Light is followed between the layers one event at a time: refracted into the coating,
+/// absorbed on its way through, scattered by the base, and either refracted out or reflected
+/// back down at the interface. A transmissive base passes light on into the object, and light
+/// leaving the object crosses the coating on its way out
+pub struct Coated {
+    base: Arc<dyn Material>,
+    ir: f32,
+    roughness: Arc<dyn Texture>,
+    absorption: color,
+    thickness: f32,
+}
+
+impl Coated {
+    pub fn from(base: Arc<dyn Material>, index_of_refraction: f32) -> Self {
+        Self {
+            base,
+            ir: index_of_refraction,
+            roughness: Arc::new(SolidColor::from(color::new())),
+            absorption: color::new(),
+            thickness: 0.,
+        }
+    }
+    pub fn with_roughness(self, roughness: f32) -> Self {
+        self.with_roughness_texture(Arc::new(SolidColor::from(color::from(
+            roughness, roughness, roughness,
+        ))))
+    }
+    pub fn with_roughness_texture(mut self, roughness: Arc<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+    /// Tints the coating, `sigma` being the absorption per unit length across a layer of
+    /// `thickness`
+    pub fn with_absorption(mut self, sigma: color, thickness: f32) -> Self {
+        self.absorption = sigma;
+        self.thickness = thickness;
+        self
+    }
+
+    /// Transmittance of a straight crossing of the coating along `d`, in the local frame
+    fn crossing(&self, d: Vec3) -> color {
+        if self.thickness <= 0. {
+            return color::from(1., 1., 1.);
+        }
+        transmittance(self.absorption, self.thickness / d.z().abs().max(1e-4))
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let alpha = roughness_to_alpha(self.roughness.value(rec.u, rec.v, &rec.p).x());
+        let outward = if rec.front_face {
+            rec.normal
+        } else {
+            rec.normal.inv()
+        };
+        let up = Onb::build_from_w(outward);
+        let down = Onb::build_from_w(outward.inv());
+
+        // Every ray keeps the hero wavelength, which a dispersive base may depend on
+        let ray = |d: Vec3| Ray::new(rec.p, d, r_in.time()).with_wavelength(r_in.wavelength());
+        let record = |specular: bool, throughput: color, d: Vec3| {
+            if specular {
+                ScatterRecord::specular(throughput, ray(d))
+            } else {
+                ScatterRecord::diffuse(throughput, ray(d))
+            }
+        };
+
+        // Between the layers `d` heads either up to the interface or down onto the base
+        let (mut d, mut going_up, mut throughput, mut specular);
+        if rec.front_face {
+            let wo = up.to_local(unit_vector(r_in.direction()).inv());
+            if wo.z() <= 0. {
+                return None;
+            }
+            let (wi, weight) = sample_rough_interface(wo, alpha, self.ir)?;
+            if wi.z() > 0. {
+                return Some(scatter_record(
+                    alpha,
+                    color::from(weight, weight, weight),
+                    ray(up.local_vec(wi)),
+                ));
+            }
+            d = up.local_vec(wi);
+            going_up = false;
+            throughput = color::from(weight, weight, weight);
+            specular = alpha < SMOOTH_ALPHA;
+        } else {
+            // Leaving the object, light goes through the base before the coating
+            let srec = self.base.scatter(r_in, rec)?;
+            d = unit_vector(srec.scattered.direction());
+            if dot(d, outward) <= 0. {
+                return Some(srec);
+            }
+            going_up = true;
+            throughput = srec.attenuation;
+            specular = srec.sampling == SamplingType::Specular;
+        }
+
+        // Inside the coating the base sees a surface hit from above, at the same point
+        let base_rec = HitRecord {
+            normal: outward,
+            mat_ptr: &*self.base,
+            front_face: true,
+            ..*rec
+        };
+        for _ in 0..MAX_BOUNCES {
+            throughput = throughput * self.crossing(up.to_local(d));
+            if going_up {
+                // At the interface from below, either out or reflected back down
+                let (wi, weight) =
+                    sample_rough_interface(down.to_local(d.inv()), alpha, 1. / self.ir)?;
+                throughput = throughput * weight;
+                specular &= alpha < SMOOTH_ALPHA;
+                d = down.local_vec(wi);
+                if dot(d, outward) > 0. {
+                    return Some(record(specular, throughput, d));
+                }
+            } else {
+                let srec = self.base.scatter(&ray(d), &base_rec)?;
+                throughput = throughput * srec.attenuation;
+                specular &= srec.sampling == SamplingType::Specular;
+                d = unit_vector(srec.scattered.direction());
+                // Transmitted by the base into the object
+                if dot(d, outward) <= 0. {
+                    return Some(record(specular, throughput, d));
+                }
+            }
+            going_up = !going_up;
+        }
+        None
+    }
+    fn emitted(&self, u: f32, v: f32, p: &point3) -> color {
+        self.base.emitted(u, v, p)
+    }
+    fn absorption(&self) -> color {
+        self.base.absorption()
+    }
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clear_coat_over_white_keeps_the_energy() {
+        let white: Arc<dyn Material> = Arc::new(Lambertian::from(color::from(1., 1., 1.)));
+        let r = Ray::new(point3::from(0., 1., 1.), Vec3::from(0., -1., -1.), 0.);
+        for &roughness in &[0., 0.3] {
+            let coated = Coated::from(white.clone(), 1.5).with_roughness(roughness);
+            let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), &coated);
+            let n = 20_000;
+            let mut total = 0.;
+            for _ in 0..n {
+                if let Some(srec) = coated.scatter(&r, &rec) {
+                    assert!(srec.scattered.direction().y() > 0.);
+                    total += srec.attenuation.x();
+                }
+            }
+            let mean = total / n as f32;
+            assert!(mean > 0.85 && mean < 1.01, "{}", mean);
+        }
+
+        // An absorbing coating darkens the base more than its own reflection
+        let varnish = Coated::from(white, 1.5).with_absorption(color::from(0., 2., 4.), 0.5);
+        let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), &varnish);
+        let mean = (0..5000).fold(color::new(), |acc, _| {
+            acc + varnish
+                .scatter(&r, &rec)
+                .map_or(color::new(), |s| s.attenuation)
+        }) / 5000.;
+        assert!(mean.x() > mean.y() && mean.y() > mean.z(), "{:?}", mean);
+    }
+
+    #[test]
+    fn clear_coat_over_glass_lets_the_light_through() {
+        let glass: Arc<dyn Material> = Arc::new(Dielectric::new(1.5));
+        let coated = Coated::from(glass, 1.5);
+        let n = 20_000;
+
+        // From outside, into the glass or back out, without losing energy either way
+        let r = Ray::new(point3::from(0., 1., 1.), Vec3::from(0., -1., -1.), 0.);
+        let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), &coated);
+        let (mut total, mut transmitted) = (0., 0);
+        for _ in 0..n {
+            if let Some(srec) = coated.scatter(&r, &rec) {
+                total += srec.attenuation.x();
+                if srec.scattered.direction().y() < 0. {
+                    transmitted += 1;
+                }
+            }
+        }
+        assert!((total / n as f32 - 1.).abs() < 0.01, "{}", total / n as f32);
+        assert!(transmitted > n / 2, "{}", transmitted);
+
+        // From inside, out through the coating
+        let r = Ray::new(point3::from(0., -1., 0.), Vec3::from(0., 1., 0.), 0.);
+        let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), &coated);
+        assert!(!rec.front_face);
+        let (mut total, mut escaped) = (0., 0);
+        for _ in 0..n {
+            if let Some(srec) = coated.scatter(&r, &rec) {
+                total += srec.attenuation.x();
+                if srec.scattered.direction().y() > 0. {
+                    escaped += 1;
+                }
+            }
+        }
+        assert!((total / n as f32 - 1.).abs() < 0.01, "{}", total / n as f32);
+        assert!(escaped > n / 2, "{}", escaped);
+    }
+}
@@ -1,17 +1,67 @@
-use crate::{objects::hittable::HitRecord, ray::*, texture::*, utils::*, vec3::*};
+use crate::{
+    material::diffuse::scatter_bsdf, material::thin_film::*, objects::hittable::HitRecord,
+    phase::*, ray::*, texture::*, utils::*, vec3::*,
+};
 use std::sync::Arc;
 
+/// How a scattered direction was chosen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SamplingType {
+    /// Drawn from a continuous distribution over directions
+    Diffuse,
+    /// The single direction of a perfect mirror or refraction
+    Specular,
+}
+
+#[derive(Copy, Clone)]
+pub struct ScatterRecord {
+    pub attenuation: color,
+    pub scattered: Ray,
+    pub sampling: SamplingType,
+}
+
+impl ScatterRecord {
+    pub fn diffuse(attenuation: color, scattered: Ray) -> Self {
+        Self {
+            attenuation,
+            scattered,
+            sampling: SamplingType::Diffuse,
+        }
+    }
+    pub fn specular(attenuation: color, scattered: Ray) -> Self {
+        Self {
+            attenuation,
+            scattered,
+            sampling: SamplingType::Specular,
+        }
+    }
+}
+
 pub trait Material: Send + Sync {
-    fn scatter(
-        &self,
-        r_in: Ray,
-        rec: HitRecord,
-        attenuation: &mut color,
-        scattered: &mut Ray,
-    ) -> bool;
-    fn emitted(&self, u: f32, v: f32, p: &point3) -> color {
+    /// Samples the continuation of `r_in` at `rec`, `None` when the ray is absorbed
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+    fn emitted(&self, _u: f32, _v: f32, _p: &point3) -> color {
+        color::new()
+    }
+    /// Beer–Lambert absorption coefficient of the inside of a closed surface, per unit length.
+    /// The integrator applies it to ray segments ending on a back face
+    fn absorption(&self) -> color {
         color::new()
     }
+    /// Whether scattering depends on `Ray::wavelength`, so a spectral path can only follow
+    /// its hero wavelength past this material
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+}
+
+/// Fraction of light left after travelling `distance` through a medium of coefficient `sigma`
+pub fn transmittance(sigma: color, distance: f32) -> color {
+    color::from(
+        (-sigma.x() * distance).exp(),
+        (-sigma.y() * distance).exp(),
+        (-sigma.z() * distance).exp(),
+    )
 }
 #[derive(Clone)]
 pub struct Isotropic {
@@ -30,17 +80,58 @@ impl Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(
-        &self,
-        r_in: Ray,
-        rec: HitRecord,
-        attenuation: &mut color,
-        scattered: &mut Ray,
-    ) -> bool {
-        *scattered = Ray::new(rec.p, Vec3::random_in_unit_sphere(), r_in.time());
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        Some(ScatterRecord::diffuse(
+            self.albedo.value(rec.u, rec.v, &rec.p),
+            Ray::new(rec.p, Vec3::random_in_unit_sphere(), r_in.time()),
+        ))
+    }
+}
+
+/// Participating medium scattering along a phase function
+#[derive(Clone)]
+pub struct Anisotropic {
+    albedo: Arc<dyn Texture>,
+    emit: Option<Arc<dyn Texture>>,
+    phase: Arc<dyn PhaseFunction>,
+}
 
-        true
+impl Anisotropic {
+    pub fn from_color(c: color, phase: Arc<dyn PhaseFunction>) -> Self {
+        Self::from_texture(Arc::new(SolidColor::from(c)), phase)
+    }
+    pub fn from_texture(a: Arc<dyn Texture>, phase: Arc<dyn PhaseFunction>) -> Self {
+        Self {
+            albedo: a,
+            emit: None,
+            phase,
+        }
+    }
+    /// Radiance added at every scattering event inside the medium
+    pub fn with_emission(mut self, e: Arc<dyn Texture>) -> Self {
+        self.emit = Some(e);
+        self
+    }
+}
+
+impl Material for Anisotropic {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let wi = unit_vector(r_in.direction());
+        let (direction, pdf) = self.phase.sample(wi);
+        if pdf <= 0. {
+            return None;
+        }
+
+        Some(ScatterRecord::diffuse(
+            self.albedo.value(rec.u, rec.v, &rec.p) * (self.phase.p(wi, direction) / pdf),
+            Ray::new(rec.p, direction, r_in.time()),
+        ))
+    }
+    fn emitted(&self, u: f32, v: f32, p: &point3) -> color {
+        match &self.emit {
+            Some(e) => e.value(u, v, p),
+            None => color::new(),
+        }
     }
 }
 
@@ -59,22 +150,8 @@ impl Lambertian {
     }
 }
 impl Material for Lambertian {
-    fn scatter(
-        &self,
-        r_in: Ray,
-        rec: HitRecord,
-        attenuation: &mut color,
-        scattered: &mut Ray,
-    ) -> bool {
-        let mut scatter_direction = rec.normal + color::random_unit_vector();
-
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
-        }
-
-        *scattered = Ray::new(rec.p, scatter_direction, r_in.time());
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        true
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        scatter_bsdf(self, r_in, rec)
     }
 }
 #[derive(Copy, Clone)]
@@ -95,49 +172,92 @@ impl Metal {
     }
 }
 impl Material for Metal {
-    fn scatter(
-        &self,
-        r_in: Ray,
-        rec: HitRecord,
-        attenuation: &mut color,
-        scattered: &mut Ray,
-    ) -> bool {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         let reflected = reflect(unit_vector(r_in.direction()), rec.normal);
-        *scattered = Ray::new(
+        let scattered = Ray::new(
             rec.p,
             reflected + Vec3::random_in_unit_sphere() * self.fuzz,
             r_in.time(),
         );
-        *attenuation = self.albedo;
-        dot(scattered.direction(), rec.normal) > 0.
+        if dot(scattered.direction(), rec.normal) <= 0. {
+            return None;
+        }
+        Some(ScatterRecord::specular(self.albedo, scattered))
     }
 }
 
-#[derive(Copy, Clone)]
+/// Index of refraction as a function of the wavelength in nanometers
+#[derive(Copy, Clone, Debug)]
+pub enum Dispersion {
+    /// `a + b / λ²`, with λ in micrometers
+    Cauchy { a: f32, b: f32 },
+    /// `n² = 1 + Σ bᵢ λ² / (λ² - cᵢ)`, with λ in micrometers
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Dispersion {
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let l2 = (wavelength * 1e-3).powi(2);
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                (1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>()).sqrt()
+            }
+        }
+    }
+}
+
+/// Sellmeier coefficients of the common borosilicate crown glass BK7
+pub const BK7: Dispersion = Dispersion::Sellmeier {
+    b: [1.039612, 0.2317923, 1.010469],
+    c: [0.006000699, 0.02001791, 103.5607],
+};
+
+#[derive(Clone)]
 pub struct Dielectric {
+    /// Index of refraction, at 550 nm for a dispersive glass
     pub ir: f32,
+    pub absorption: color,
+    pub dispersion: Option<Dispersion>,
+    pub film: Option<ThinFilm>,
 }
 impl Dielectric {
     pub fn new(index_of_refraction: f32) -> Self {
         Self {
             ir: index_of_refraction,
+            absorption: color::new(),
+            dispersion: None,
+            film: None,
         }
     }
+    /// Glass whose index varies with the wavelength when rendering spectrally
+    pub fn dispersive(dispersion: Dispersion) -> Self {
+        Self {
+            dispersion: Some(dispersion),
+            ..Self::new(dispersion.ior(550.))
+        }
+    }
+    fn ior(&self, wavelength: f32) -> f32 {
+        match self.dispersion {
+            Some(d) if wavelength > 0. => d.ior(wavelength),
+            _ => self.ir,
+        }
+    }
+    /// Tints the glass by thickness, `sigma` being the absorption per unit length
+    pub fn with_absorption(mut self, sigma: color) -> Self {
+        self.absorption = sigma;
+        self
+    }
+    /// Coats both sides of the surface, as the wall of a soap bubble when `ir` is 1
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
 }
 impl Material for Dielectric {
-    fn scatter(
-        &self,
-        r_in: Ray,
-        rec: HitRecord,
-        attenuation: &mut color,
-        scattered: &mut Ray,
-    ) -> bool {
-        *attenuation = color::from(1., 1., 1.);
-        let refraction_ratio = if rec.front_face {
-            1. / self.ir
-        } else {
-            self.ir
-        };
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let ir = self.ior(r_in.wavelength());
+        let refraction_ratio = if rec.front_face { 1. / ir } else { ir };
 
         let unit_direction = unit_vector(r_in.direction());
 
@@ -146,6 +266,32 @@ impl Material for Dielectric {
 
         let cannot_refract = refraction_ratio * sin_theta > 1.;
 
+        if let Some(film) = &self.film {
+            // Interference colors the reflection, so the event is chosen by the mean
+            // reflectance and the attenuation carries the tint
+            let (n_outer, n_inner) = if rec.front_face { (1., ir) } else { (ir, 1.) };
+            let r = film.reflectance(
+                rec,
+                cos_theta,
+                n_outer,
+                [(n_inner, 0.); 3],
+                r_in.wavelength(),
+            );
+            let p = (r.x() + r.y() + r.z()) / 3.;
+            let (direction, attenuation) = if cannot_refract || p > random_double(0., 1.) {
+                (reflect(unit_direction, rec.normal), r / p)
+            } else {
+                (
+                    refract(unit_direction, rec.normal, refraction_ratio),
+                    (color::from(1., 1., 1.) - r) / (1. - p),
+                )
+            };
+            return Some(ScatterRecord::specular(
+                attenuation,
+                Ray::new(rec.p, direction, r_in.time()),
+            ));
+        }
+
         let direction =
             if cannot_refract || reflectance(cos_theta, refraction_ratio) > random_double(0., 1.) {
                 reflect(unit_direction, rec.normal)
@@ -153,9 +299,17 @@ impl Material for Dielectric {
                 refract(unit_direction, rec.normal, refraction_ratio)
             };
 
-        *scattered = Ray::new(rec.p, direction, r_in.time());
-
-        true
+        Some(ScatterRecord::specular(
+            color::from(1., 1., 1.),
+            Ray::new(rec.p, direction, r_in.time()),
+        ))
+    }
+    fn absorption(&self) -> color {
+        self.absorption
+    }
+    /// Films interfere differently at each wavelength even over a glass of constant index
+    fn is_dispersive(&self) -> bool {
+        self.dispersion.is_some() || self.film.is_some()
     }
 }
 fn reflectance(cosine: f32, ref_idx: f32) -> f32 {
@@ -179,14 +333,8 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(
-        &self,
-        r_in: Ray,
-        rec: HitRecord,
-        attenuation: &mut color,
-        scattered: &mut Ray,
-    ) -> bool {
-        false
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+        None
     }
     fn emitted(&self, u: f32, v: f32, p: &point3) -> color {
         self.emit.value(u, v, p)
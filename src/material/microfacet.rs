@@ -0,0 +1,413 @@
+use crate::{
+    material::material::*, material::thin_film::*, objects::hittable::HitRecord, onb::*, ray::*,
+    texture::*, utils::*, vec3::*,
+};
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Below this GGX alpha a lobe is treated as a perfect mirror
+pub(crate) const SMOOTH_ALPHA: f32 = 1e-3;
+
+/// GGX alpha from a perceptual roughness in `0..1`
+pub(crate) fn roughness_to_alpha(roughness: f32) -> f32 {
+    let r = clamp(roughness, 0., 1.);
+    (r * r).max(1e-4)
+}
+
+/// Smith Λ of the GGX distribution for a direction in the local frame of the normal
+pub(crate) fn ggx_lambda(w: Vec3, alpha: f32) -> f32 {
+    let cos2 = w.z() * w.z();
+    if cos2 <= 0. {
+        return 0.;
+    }
+    let tan2 = (1. - cos2).max(0.) / cos2;
+    0.5 * (-1. + (1. + alpha * alpha * tan2).sqrt())
+}
+
+pub(crate) fn smith_g1(w: Vec3, alpha: f32) -> f32 {
+    1. / (1. + ggx_lambda(w, alpha))
+}
+
+/// Height-correlated masking-shadowing of the pair of directions
+pub(crate) fn smith_g2(wo: Vec3, wi: Vec3, alpha: f32) -> f32 {
+    1. / (1. + ggx_lambda(wo, alpha) + ggx_lambda(wi, alpha))
+}
+
+/// Microfacet normal drawn from the GGX normals visible from `wo` (Heitz 2018), in the local
+/// frame where the macro normal is +z
+pub(crate) fn sample_ggx_vndf(wo: Vec3, alpha: f32) -> Vec3 {
+    let (u1, u2) = (random_double(0., 1.), random_double(0., 1.));
+
+    // Stretch the view so the distribution becomes a hemisphere
+    let vh = unit_vector(Vec3::from(alpha * wo.x(), alpha * wo.y(), wo.z()));
+    let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1 = if lensq > 0. {
+        Vec3::from(-vh.y(), vh.x(), 0.) / lensq.sqrt()
+    } else {
+        Vec3::from(1., 0., 0.)
+    };
+    let t2 = cross(vh, t1);
+
+    let r = u1.sqrt();
+    let phi = 2. * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1. + vh.z());
+    let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * (1. - p1 * p1 - p2 * p2).max(0.).sqrt();
+
+    unit_vector(Vec3::from(alpha * nh.x(), alpha * nh.y(), nh.z().max(0.)))
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric boundary, `eta` being the ratio of the
+/// index on the far side over the index on the incident side
+pub(crate) fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let cos_i = clamp(cos_i, 0., 1.);
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+pub(crate) fn fresnel_schlick(f0: color, cos_i: f32) -> color {
+    let x = (1. - clamp(cos_i, 0., 1.)).powi(5);
+    f0 + (color::from(1., 1., 1.) - f0) * x
+}
+
+/// Exact unpolarized Fresnel reflectance of a conductor of complex index `eta + i k`
+pub(crate) fn fresnel_conductor(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = clamp(cos_i, 0., 1.).powi(2);
+    let sin2 = 1. - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2plusb2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+    let t1 = a2plusb2 + cos2;
+    let a = (0.5 * (a2plusb2 + t0)).max(0.).sqrt();
+    let t2 = 2. * cos2.sqrt() * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2plusb2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rp + rs)
+}
+
+/// Complex index of refraction `(eta, k)` of common metals, sampled at 650, 550 and 450 nm
+pub const METALS: [(&str, [f32; 3], [f32; 3]); 6] = [
+    ("Au", [0.143, 0.374, 1.442], [3.983, 2.386, 1.603]),
+    ("Ag", [0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+    ("Cu", [0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+    ("Al", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+    ("Cr", [3.105, 3.180, 2.392], [3.317, 3.330, 3.187]),
+    ("Ti", [2.741, 2.541, 2.264], [3.814, 3.435, 3.038]),
+];
+
+fn roughness_at(roughness: &Arc<dyn Texture>, rec: &HitRecord) -> f32 {
+    roughness_to_alpha(roughness.value(rec.u, rec.v, &rec.p).x())
+}
+
+/// Reflects or refracts `wo` off a microfacet of a rough dielectric interface, `eta` being the
+/// ratio of the index across the interface over the index on the side of `wo`. Returns the new
+/// direction, in the same local frame, with its weight
+pub(crate) fn sample_rough_interface(wo: Vec3, alpha: f32, eta: f32) -> Option<(Vec3, f32)> {
+    let m = sample_ggx_vndf(wo, alpha);
+    let wi = if fresnel_dielectric(dot(wo, m), eta) > random_double(0., 1.) {
+        let wi = reflect(wo.inv(), m);
+        if wi.z() <= 0. {
+            return None;
+        }
+        wi
+    } else {
+        let wi = refract(wo.inv(), m, 1. / eta);
+        if wi.z() >= 0. {
+            return None;
+        }
+        wi
+    };
+
+    // Choosing the event by its Fresnel weight cancels the Fresnel factor
+    Some((wi, smith_g2(wo, wi, alpha) / smith_g1(wo, alpha)))
+}
+
+/// Marks nearly smooth lobes as specular so callers can tell a delta from a distribution
+pub(crate) fn scatter_record(alpha: f32, attenuation: color, scattered: Ray) -> ScatterRecord {
+    if alpha < SMOOTH_ALPHA {
+        ScatterRecord::specular(attenuation, scattered)
+    } else {
+        ScatterRecord::diffuse(attenuation, scattered)
+    }
+}
+
+enum ConductorFresnel {
+    /// Schlick's approximation from the reflectance at normal incidence
+    Albedo(Arc<dyn Texture>),
+    /// Exact Fresnel of a complex index of refraction per channel
+    Complex { eta: color, k: color },
+}
+
+/// Rough metal with a GGX distribution of microfacets. The roughness texture is read from its
+/// first channel
+pub struct RoughConductor {
+    fresnel: ConductorFresnel,
+    roughness: Arc<dyn Texture>,
+    film: Option<ThinFilm>,
+}
+
+impl RoughConductor {
+    /// Metal reflecting `albedo` at normal incidence
+    pub fn from(albedo: color, roughness: f32) -> Self {
+        Self::from_texture(
+            Arc::new(SolidColor::from(albedo)),
+            Arc::new(SolidColor::from(color::from(
+                roughness, roughness, roughness,
+            ))),
+        )
+    }
+    pub fn from_texture(albedo: Arc<dyn Texture>, roughness: Arc<dyn Texture>) -> Self {
+        Self {
+            fresnel: ConductorFresnel::Albedo(albedo),
+            roughness,
+            film: None,
+        }
+    }
+    /// Metal of complex index of refraction `eta + i k` in each RGB channel
+    pub fn from_ior(eta: color, k: color, roughness: f32) -> Self {
+        Self {
+            fresnel: ConductorFresnel::Complex { eta, k },
+            roughness: Arc::new(SolidColor::from(color::from(
+                roughness, roughness, roughness,
+            ))),
+            film: None,
+        }
+    }
+    /// One of the `METALS` by chemical symbol, such as "Au" or "Cu"
+    pub fn from_metal(name: &str, roughness: f32) -> Self {
+        let (eta, k) = match METALS.iter().find(|(symbol, _, _)| *symbol == name) {
+            Some((_, eta, k)) => (*eta, *k),
+            None => {
+                eprintln!("ERROR: unknown metal `{}`, using Al", name);
+                (METALS[3].1, METALS[3].2)
+            }
+        };
+        Self::from_ior(
+            color::from(eta[0], eta[1], eta[2]),
+            color::from(k[0], k[1], k[2]),
+            roughness,
+        )
+    }
+    pub fn with_roughness_texture(mut self, roughness: Arc<dyn Texture>) -> Self {
+        self.roughness = roughness;
+        self
+    }
+    /// Oxide or oil layer over the metal, as on tempered steel or anodized titanium
+    pub fn with_thin_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    /// Complex index of the metal in each channel
+    fn substrate(&self, rec: &HitRecord) -> [(f32, f32); 3] {
+        match &self.fresnel {
+            ConductorFresnel::Albedo(albedo) => {
+                // Real index giving the same reflectance at normal incidence
+                let f0 = albedo.value(rec.u, rec.v, &rec.p);
+                let eta = |f: f32| {
+                    let s = clamp(f, 0., 0.999).sqrt();
+                    ((1. + s) / (1. - s), 0.)
+                };
+                [eta(f0.x()), eta(f0.y()), eta(f0.z())]
+            }
+            ConductorFresnel::Complex { eta, k } => {
+                [(eta.x(), k.x()), (eta.y(), k.y()), (eta.z(), k.z())]
+            }
+        }
+    }
+    /// Reflectance at `cos_i`, evaluated at the hero `wavelength` through a film if non-zero
+    fn fresnel(&self, rec: &HitRecord, cos_i: f32, wavelength: f32) -> color {
+        if let Some(film) = &self.film {
+            return film.reflectance(rec, cos_i, 1., self.substrate(rec), wavelength);
+        }
+        match &self.fresnel {
+            ConductorFresnel::Albedo(albedo) => {
+                fresnel_schlick(albedo.value(rec.u, rec.v, &rec.p), cos_i)
+            }
+            ConductorFresnel::Complex { eta, k } => color::from(
+                fresnel_conductor(cos_i, eta.x(), k.x()),
+                fresnel_conductor(cos_i, eta.y(), k.y()),
+                fresnel_conductor(cos_i, eta.z(), k.z()),
+            ),
+        }
+    }
+}
+
+impl Material for RoughConductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let alpha = roughness_at(&self.roughness, rec);
+        let frame = Onb::build_from_w(rec.normal);
+        let wo = frame.to_local(unit_vector(r_in.direction()).inv());
+        if wo.z() <= 0. {
+            return None;
+        }
+
+        let m = sample_ggx_vndf(wo, alpha);
+        let wi = reflect(wo.inv(), m);
+        if wi.z() <= 0. {
+            return None;
+        }
+
+        // Sampling visible normals leaves only the shadowing of the outgoing direction
+        let f = self.fresnel(rec, dot(wo, m), r_in.wavelength());
+        let weight = smith_g2(wo, wi, alpha) / smith_g1(wo, alpha);
+        Some(scatter_record(
+            alpha,
+            f * weight,
+            Ray::new(rec.p, frame.local_vec(wi), r_in.time()),
+        ))
+    }
+    fn is_dispersive(&self) -> bool {
+        self.film.is_some()
+    }
+}
+
+/// Frosted glass: a GGX rough interface that reflects or refracts off each sampled microfacet
+pub struct RoughDielectric {
+    ir: f32,
+    roughness: Arc<dyn Texture>,
+    absorption: color,
+}
+
+impl RoughDielectric {
+    pub fn from(index_of_refraction: f32, roughness: f32) -> Self {
+        Self::from_texture(
+            index_of_refraction,
+            Arc::new(SolidColor::from(color::from(
+                roughness, roughness, roughness,
+            ))),
+        )
+    }
+    pub fn from_texture(index_of_refraction: f32, roughness: Arc<dyn Texture>) -> Self {
+        Self {
+            ir: index_of_refraction,
+            roughness,
+            absorption: color::new(),
+        }
+    }
+    /// Tints the glass by thickness, `sigma` being the absorption per unit length
+    pub fn with_absorption(mut self, sigma: color) -> Self {
+        self.absorption = sigma;
+        self
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let alpha = roughness_at(&self.roughness, rec);
+        let eta = if rec.front_face {
+            self.ir
+        } else {
+            1. / self.ir
+        };
+        let frame = Onb::build_from_w(rec.normal);
+        let wo = frame.to_local(unit_vector(r_in.direction()).inv());
+        if wo.z() <= 0. {
+            return None;
+        }
+
+        let (wi, weight) = sample_rough_interface(wo, alpha, eta)?;
+        Some(scatter_record(
+            alpha,
+            color::from(weight, weight, weight),
+            Ray::new(rec.p, frame.local_vec(wi), r_in.time()),
+        ))
+    }
+    fn absorption(&self) -> color {
+        self.absorption
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visible_normals_follow_the_ggx_projected_area() {
+        // The VNDF D(m) G1(wo) <wo, m> / <wo, n>, integrated numerically over the hemisphere
+        // into bins of m.z, against the same bins filled by the sampler
+        const BINS: usize = 8;
+        let views = [
+            Vec3::from(0., 0., 1.),
+            unit_vector(Vec3::from(0.6, 0.1, 0.5)),
+            unit_vector(Vec3::from(0.95, -0.2, 0.1)),
+        ];
+        for &alpha in &[0.1, 0.4, 0.8] {
+            let d = |m: Vec3| {
+                let x = (alpha * alpha - 1.) * m.z() * m.z() + 1.;
+                alpha * alpha / (PI * x * x)
+            };
+            for &wo in &views {
+                let bin = |m: Vec3| ((m.z() * BINS as f32) as usize).min(BINS - 1);
+
+                let (n_theta, n_phi) = (1024, 256);
+                let (d_theta, d_phi) = (0.5 * PI / n_theta as f32, 2. * PI / n_phi as f32);
+                let mut expected = [0.; BINS];
+                let mut expected_mean = Vec3::new();
+                for i in 0..n_theta {
+                    let theta = (i as f32 + 0.5) * d_theta;
+                    for j in 0..n_phi {
+                        let phi = (j as f32 + 0.5) * d_phi;
+                        let m = Vec3::from(
+                            theta.sin() * phi.cos(),
+                            theta.sin() * phi.sin(),
+                            theta.cos(),
+                        );
+                        let pdf = d(m) * smith_g1(wo, alpha) * dot(wo, m).max(0.) / wo.z();
+                        let weight = pdf * theta.sin() * d_theta * d_phi;
+                        expected[bin(m)] += weight;
+                        expected_mean = expected_mean + m * weight;
+                    }
+                }
+                let total: f32 = expected.iter().sum();
+                assert!((total - 1.).abs() < 0.01, "{} {:?}: {}", alpha, wo, total);
+
+                let n = 100_000;
+                let mut sampled = [0.; BINS];
+                let mut sampled_mean = Vec3::new();
+                for _ in 0..n {
+                    let m = sample_ggx_vndf(wo, alpha);
+                    assert!(dot(wo, m) > 0. && (m.len() - 1.).abs() < 1e-4);
+                    sampled[bin(m)] += 1. / n as f32;
+                    sampled_mean = sampled_mean + m / n as f32;
+                }
+                for b in 0..BINS {
+                    assert!(
+                        (sampled[b] - expected[b]).abs() < 0.01,
+                        "{} {:?}: {:?} {:?}",
+                        alpha,
+                        wo,
+                        sampled,
+                        expected
+                    );
+                }
+                assert!((sampled_mean - expected_mean).len() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn conductor_fresnel_limits() {
+        // At normal incidence ((n - 1)² + k²) / ((n + 1)² + k²), and total at grazing angles
+        let (eta, k) = (0.2, 3.9);
+        let r0 = ((eta - 1.) * (eta - 1.) + k * k) / ((eta + 1.) * (eta + 1.) + k * k);
+        assert!((fresnel_conductor(1., eta, k) - r0).abs() < 1e-5);
+        assert!((fresnel_conductor(0., eta, k) - 1.).abs() < 1e-5);
+
+        // A conductor without extinction is a dielectric
+        for &c in &[0.1, 0.5, 0.9] {
+            assert!((fresnel_conductor(c, 1.5, 0.) - fresnel_dielectric(c, 1.5)).abs() < 1e-4);
+        }
+    }
+}
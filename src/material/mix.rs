@@ -0,0 +1,146 @@
+use crate::{
+    material::material::*, objects::hittable::HitRecord, ray::*, texture::*, utils::*, vec3::*,
+};
+use std::sync::Arc;
+
+/// Blend of two materials, like rust patches over a metal. Each hit scatters off `second`
+/// with the probability read from the first channel of `weight`, and off `first` otherwise
+pub struct MixMaterial {
+    first: Arc<dyn Material>,
+    second: Arc<dyn Material>,
+    weight: Arc<dyn Texture>,
+    /// Weight averaged over the uv square, blending the absorption of the insides
+    mean_weight: f32,
+}
+
+impl MixMaterial {
+    pub fn from(first: Arc<dyn Material>, second: Arc<dyn Material>, weight: f32) -> Self {
+        Self::from_texture(
+            first,
+            second,
+            Arc::new(SolidColor::from(color::from(weight, weight, weight))),
+        )
+    }
+    pub fn from_texture(
+        first: Arc<dyn Material>,
+        second: Arc<dyn Material>,
+        weight: Arc<dyn Texture>,
+    ) -> Self {
+        let n = 16;
+        let mut total = 0.;
+        for i in 0..n {
+            for j in 0..n {
+                let (u, v) = ((i as f32 + 0.5) / n as f32, (j as f32 + 0.5) / n as f32);
+                total += clamp(weight.value(u, v, &point3::new()).x(), 0., 1.);
+            }
+        }
+        Self {
+            first,
+            second,
+            weight,
+            mean_weight: total / (n * n) as f32,
+        }
+    }
+    fn weight(&self, u: f32, v: f32, p: &point3) -> f32 {
+        clamp(self.weight.value(u, v, p).x(), 0., 1.)
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if self.weight(rec.u, rec.v, &rec.p) > random_double(0., 1.) {
+            self.second.scatter(r_in, rec)
+        } else {
+            self.first.scatter(r_in, rec)
+        }
+    }
+    fn emitted(&self, u: f32, v: f32, p: &point3) -> color {
+        let w = self.weight(u, v, p);
+        self.first.emitted(u, v, p) * (1. - w) + self.second.emitted(u, v, p) * w
+    }
+    /// The inside has no hit point to read the weight at, so the mean weight blends the two
+    fn absorption(&self) -> color {
+        let w = self.mean_weight;
+        self.first.absorption() * (1. - w) + self.second.absorption() * w
+    }
+    fn is_dispersive(&self) -> bool {
+        self.first.is_dispersive() || self.second.is_dispersive()
+    }
+}
+
+/// Surface cut out where the first channel of `opacity` is zero, like leaves or a fence on a
+/// single quad. Rays go straight through with the probability of the transparency
+pub struct Cutout {
+    base: Arc<dyn Material>,
+    opacity: Arc<dyn Texture>,
+}
+
+impl Cutout {
+    pub fn from(base: Arc<dyn Material>, opacity: Arc<dyn Texture>) -> Self {
+        Self { base, opacity }
+    }
+    fn opacity(&self, u: f32, v: f32, p: &point3) -> f32 {
+        clamp(self.opacity.value(u, v, p).x(), 0., 1.)
+    }
+}
+
+impl Material for Cutout {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        if self.opacity(rec.u, rec.v, &rec.p) > random_double(0., 1.) {
+            return self.base.scatter(r_in, rec);
+        }
+        Some(ScatterRecord::specular(
+            color::from(1., 1., 1.),
+            Ray::new(rec.p, r_in.direction(), r_in.time()).with_wavelength(r_in.wavelength()),
+        ))
+    }
+    fn emitted(&self, u: f32, v: f32, p: &point3) -> color {
+        self.base.emitted(u, v, p) * self.opacity(u, v, p)
+    }
+    fn absorption(&self) -> color {
+        self.base.absorption()
+    }
+    fn is_dispersive(&self) -> bool {
+        self.base.is_dispersive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mix_and_cutout_follow_their_textures() {
+        let red: Arc<dyn Material> = Arc::new(Lambertian::from(color::from(1., 0., 0.)));
+        let blue: Arc<dyn Material> = Arc::new(Lambertian::from(color::from(0., 0., 1.)));
+        let r = Ray::new(point3::from(0., 1., 1.), Vec3::from(0., -1., -1.), 0.);
+
+        let mix = MixMaterial::from(red.clone(), blue, 0.25);
+        let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), &mix);
+        let n = 20_000;
+        let mean = (0..n).fold(color::new(), |acc, _| {
+            acc + mix.scatter(&r, &rec).unwrap().attenuation
+        }) / n as f32;
+        assert!((mean.x() - 0.75).abs() < 0.02 && (mean.z() - 0.25).abs() < 0.02);
+
+        // Fully transparent: the ray carries on untouched from the hit point
+        let hole = Cutout::from(red, Arc::new(SolidColor::from(color::new())));
+        let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), &hole);
+        let srec = hole.scatter(&r, &rec).unwrap();
+        assert_eq!(srec.sampling, SamplingType::Specular);
+        assert!((srec.scattered.direction() - r.direction()).len() < 1e-6);
+        assert!((srec.scattered.origin() - rec.p).len() < 1e-6);
+    }
+
+    #[test]
+    fn wrappers_keep_the_tint_of_the_glass() {
+        let sigma = color::from(0.4, 0.8, 1.2);
+        let tinted: Arc<dyn Material> = Arc::new(Dielectric::new(1.5).with_absorption(sigma));
+        let clear: Arc<dyn Material> = Arc::new(Dielectric::new(1.5));
+
+        let cutout = Cutout::from(tinted.clone(), Arc::new(SolidColor::from(color::new())));
+        assert!((cutout.absorption() - sigma).len() < 1e-6);
+        let mix = MixMaterial::from(clear, tinted, 0.25);
+        assert!((mix.absorption() - sigma * 0.25).len() < 1e-6);
+    }
+}
@@ -1,2 +1,10 @@
 pub mod boxx;
+pub mod bump;
+pub mod diffuse;
+pub mod hair;
+pub mod layered;
+pub mod microfacet;
+pub mod mix;
+pub mod principled;
+pub mod thin_film;
 pub mod material;
@@ -0,0 +1,248 @@
+use crate::{
+    material::material::*, material::microfacet::*, objects::hittable::HitRecord, onb::*, ray::*,
+    texture::*, utils::*, vec3::*,
+};
+use std::sync::Arc;
+
+/// Inputs of `Principled` that can be driven by a texture. Scalar inputs read the first channel
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PrincipledInput {
+    BaseColor,
+    Metallic,
+    Roughness,
+    Specular,
+    Clearcoat,
+    ClearcoatRoughness,
+    Sheen,
+    Transmission,
+}
+
+/// Principled BSDF in the terms of glTF and Blender: a clearcoat over either a metal or a
+/// dielectric base, the latter splitting into specular, transmission and diffuse with sheen.
+/// Each scatter picks one lobe with the probability of the energy it carries
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: Arc<dyn Texture>,
+    roughness: Arc<dyn Texture>,
+    specular: Arc<dyn Texture>,
+    clearcoat: Arc<dyn Texture>,
+    clearcoat_roughness: Arc<dyn Texture>,
+    sheen: Arc<dyn Texture>,
+    transmission: Arc<dyn Texture>,
+    ir: f32,
+    emit: Option<Arc<dyn Texture>>,
+}
+
+fn constant(x: f32) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::from(color::from(x, x, x)))
+}
+
+impl Principled {
+    pub fn from(base_color: color) -> Self {
+        Self::from_texture(Arc::new(SolidColor::from(base_color)))
+    }
+    pub fn from_texture(base_color: Arc<dyn Texture>) -> Self {
+        Self {
+            base_color,
+            metallic: constant(0.),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            clearcoat: constant(0.),
+            clearcoat_roughness: constant(0.03),
+            sheen: constant(0.),
+            transmission: constant(0.),
+            ir: 1.5,
+            emit: None,
+        }
+    }
+    pub fn with_metallic(self, metallic: f32) -> Self {
+        self.with_texture(PrincipledInput::Metallic, constant(metallic))
+    }
+    pub fn with_roughness(self, roughness: f32) -> Self {
+        self.with_texture(PrincipledInput::Roughness, constant(roughness))
+    }
+    /// Scales the dielectric reflectance, 0.5 being the physical value of `ior`
+    pub fn with_specular(self, specular: f32) -> Self {
+        self.with_texture(PrincipledInput::Specular, constant(specular))
+    }
+    pub fn with_clearcoat(self, clearcoat: f32, roughness: f32) -> Self {
+        self.with_texture(PrincipledInput::Clearcoat, constant(clearcoat))
+            .with_texture(PrincipledInput::ClearcoatRoughness, constant(roughness))
+    }
+    /// Colored grazing-angle reflection of cloth
+    pub fn with_sheen(self, sheen: color) -> Self {
+        self.with_texture(PrincipledInput::Sheen, Arc::new(SolidColor::from(sheen)))
+    }
+    pub fn with_transmission(self, transmission: f32) -> Self {
+        self.with_texture(PrincipledInput::Transmission, constant(transmission))
+    }
+    pub fn with_ior(mut self, index_of_refraction: f32) -> Self {
+        self.ir = index_of_refraction;
+        self
+    }
+    pub fn with_emission(mut self, e: Arc<dyn Texture>) -> Self {
+        self.emit = Some(e);
+        self
+    }
+    pub fn with_texture(mut self, input: PrincipledInput, tex: Arc<dyn Texture>) -> Self {
+        match input {
+            PrincipledInput::BaseColor => self.base_color = tex,
+            PrincipledInput::Metallic => self.metallic = tex,
+            PrincipledInput::Roughness => self.roughness = tex,
+            PrincipledInput::Specular => self.specular = tex,
+            PrincipledInput::Clearcoat => self.clearcoat = tex,
+            PrincipledInput::ClearcoatRoughness => self.clearcoat_roughness = tex,
+            PrincipledInput::Sheen => self.sheen = tex,
+            PrincipledInput::Transmission => self.transmission = tex,
+        }
+        self
+    }
+}
+
+fn scalar(tex: &Arc<dyn Texture>, rec: &HitRecord) -> f32 {
+    clamp(tex.value(rec.u, rec.v, &rec.p).x(), 0., 1.)
+}
+
+/// GGX reflection of `wo` off a sampled microfacet, with the Fresnel factor left to the caller
+fn glossy_reflection(wo: Vec3, alpha: f32) -> Option<(Vec3, Vec3, f32)> {
+    let m = sample_ggx_vndf(wo, alpha);
+    let wi = reflect(wo.inv(), m);
+    if wi.z() <= 0. {
+        return None;
+    }
+    Some((m, wi, smith_g2(wo, wi, alpha) / smith_g1(wo, alpha)))
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        // Leaving a transmissive body only the rough interface remains. Other back faces, as of
+        // an open or thin surface, shade like front faces since the normal faces the ray
+        let transmission = scalar(&self.transmission, rec);
+        if !rec.front_face && transmission > random_double(0., 1.) {
+            return RoughDielectric::from_texture(self.ir, self.roughness.clone())
+                .scatter(r_in, rec);
+        }
+
+        let frame = Onb::build_from_w(rec.normal);
+        let wo = frame.to_local(unit_vector(r_in.direction()).inv());
+        if wo.z() <= 0. {
+            return None;
+        }
+        let scattered = |wi: Vec3| Ray::new(rec.p, frame.local_vec(wi), r_in.time());
+
+        let base = self.base_color.value(rec.u, rec.v, &rec.p);
+        let alpha = roughness_to_alpha(scalar(&self.roughness, rec));
+
+        let clearcoat = scalar(&self.clearcoat, rec);
+        if clearcoat > 0. && clearcoat * fresnel_dielectric(wo.z(), 1.5) > random_double(0., 1.) {
+            let alpha = roughness_to_alpha(scalar(&self.clearcoat_roughness, rec));
+            let (_, wi, weight) = glossy_reflection(wo, alpha)?;
+            return Some(scatter_record(
+                alpha,
+                color::from(weight, weight, weight),
+                scattered(wi),
+            ));
+        }
+
+        if scalar(&self.metallic, rec) > random_double(0., 1.) {
+            let (m, wi, weight) = glossy_reflection(wo, alpha)?;
+            return Some(scatter_record(
+                alpha,
+                fresnel_schlick(base, dot(wo, m)) * weight,
+                scattered(wi),
+            ));
+        }
+
+        // Dielectric base: specular off the microfacets, the rest refracts into the surface
+        let m = sample_ggx_vndf(wo, alpha);
+        let specular = scalar(&self.specular, rec);
+        let f = (2. * specular * fresnel_dielectric(dot(wo, m), self.ir)).min(1.);
+        if f > random_double(0., 1.) {
+            let wi = reflect(wo.inv(), m);
+            if wi.z() <= 0. {
+                return None;
+            }
+            let weight = smith_g2(wo, wi, alpha) / smith_g1(wo, alpha);
+            return Some(scatter_record(
+                alpha,
+                color::from(weight, weight, weight),
+                scattered(wi),
+            ));
+        }
+        if rec.front_face && transmission > random_double(0., 1.) {
+            let wi = refract(wo.inv(), m, 1. / self.ir);
+            if wi.z() >= 0. {
+                return None;
+            }
+            let weight = smith_g2(wo, wi, alpha) / smith_g1(wo, alpha);
+            return Some(scatter_record(alpha, base * weight, scattered(wi)));
+        }
+
+        let mut scatter_direction = rec.normal + Vec3::random_unit_vector();
+        if scatter_direction.near_zero() {
+            scatter_direction = rec.normal;
+        }
+        let wi = unit_vector(frame.to_local(scatter_direction));
+        let h = unit_vector(wo + wi);
+        let sheen = self.sheen.value(rec.u, rec.v, &rec.p) * (1. - dot(wi, h)).powi(5);
+        Some(ScatterRecord::diffuse(
+            base + sheen,
+            Ray::new(rec.p, scatter_direction, r_in.time()),
+        ))
+    }
+    fn emitted(&self, u: f32, v: f32, p: &point3) -> color {
+        match &self.emit {
+            Some(e) => e.value(u, v, p),
+            None => color::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_base_neither_creates_nor_loses_much_energy() {
+        let materials = vec![
+            Principled::from(color::from(1., 1., 1.)),
+            Principled::from(color::from(1., 1., 1.)).with_roughness(0.2),
+            Principled::from(color::from(1., 1., 1.))
+                .with_metallic(1.)
+                .with_roughness(0.1),
+            Principled::from(color::from(1., 1., 1.)).with_clearcoat(1., 0.05),
+            Principled::from(color::from(1., 1., 1.)).with_transmission(1.),
+        ];
+        let r = Ray::new(point3::from(0., 1., 1.), Vec3::from(0., -1., -1.), 0.);
+        for mat in &materials {
+            let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), mat);
+            let n = 20_000;
+            let total = (0..n).fold(0., |acc, _| {
+                acc + mat.scatter(&r, &rec).map_or(0., |s| s.attenuation.x())
+            });
+            let mean = total / n as f32;
+            assert!(mean > 0.85 && mean < 1.01, "{}", mean);
+        }
+    }
+
+    #[test]
+    fn opaque_back_faces_never_refract() {
+        let materials = vec![
+            Principled::from(color::from(0.8, 0.8, 0.8)),
+            Principled::from(color::from(0.8, 0.8, 0.8)).with_roughness(0.5),
+            Principled::from(color::from(0.8, 0.8, 0.8)).with_metallic(1.),
+            Principled::from(color::from(0.8, 0.8, 0.8)).with_clearcoat(1., 0.1),
+        ];
+        // Hitting the plane from below, against its upward outward normal
+        let r = Ray::new(point3::from(0., -1., 1.), Vec3::from(0., 1., -1.), 0.);
+        for mat in &materials {
+            let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), mat);
+            assert!(!rec.front_face);
+            for _ in 0..2_000 {
+                if let Some(s) = mat.scatter(&r, &rec) {
+                    assert!(s.scattered.direction().y() < 0.);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{objects::hittable::HitRecord, texture::*, vec3::*};
+use std::f32::consts::PI;
+use std::ops;
+use std::sync::Arc;
+
+/// Wavelengths in nanometers standing for the red, green and blue channels
+pub const RGB_WAVELENGTHS: [f32; 3] = [650., 550., 450.];
+
+#[derive(Copy, Clone, Debug)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+    fn norm_squared(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+    /// Principal square root
+    fn sqrt(self) -> Self {
+        let r = self.norm_squared().sqrt();
+        let re = (0.5 * (r + self.re)).max(0.).sqrt();
+        let im = (0.5 * (r - self.re)).max(0.).sqrt();
+        Self::new(re, if self.im < 0. { -im } else { im })
+    }
+    fn exp_i(phase: f32) -> Self {
+        Self::new(phase.cos(), phase.sin())
+    }
+}
+
+impl ops::Add for Complex {
+    type Output = Self;
+    fn add(self, o: Self) -> Self {
+        Self::new(self.re + o.re, self.im + o.im)
+    }
+}
+impl ops::Sub for Complex {
+    type Output = Self;
+    fn sub(self, o: Self) -> Self {
+        Self::new(self.re - o.re, self.im - o.im)
+    }
+}
+impl ops::Mul for Complex {
+    type Output = Self;
+    fn mul(self, o: Self) -> Self {
+        Self::new(
+            self.re * o.re - self.im * o.im,
+            self.re * o.im + self.im * o.re,
+        )
+    }
+}
+impl ops::Div for Complex {
+    type Output = Self;
+    fn div(self, o: Self) -> Self {
+        let d = o.norm_squared();
+        Self::new(
+            (self.re * o.re + self.im * o.im) / d,
+            (self.im * o.re - self.re * o.im) / d,
+        )
+    }
+}
+
+/// Amplitude reflection coefficients (s, p) between media `a` and `b` for the cosines of the
+/// angles on each side
+fn amplitudes(na: Complex, cos_a: Complex, nb: Complex, cos_b: Complex) -> (Complex, Complex) {
+    let rs = (na * cos_a - nb * cos_b) / (na * cos_a + nb * cos_b);
+    let rp = (nb * cos_a - na * cos_b) / (nb * cos_a + na * cos_b);
+    (rs, rp)
+}
+
+/// Reflectance of a film of index `ior` and `thickness` nanometers between an outer medium of
+/// index `n_outer` and a substrate of complex index `eta + i k`, for light of `wavelength`
+/// nanometers arriving at `cos_i`
+pub fn film_reflectance(
+    ior: f32,
+    thickness: f32,
+    cos_i: f32,
+    n_outer: f32,
+    (eta, k): (f32, f32),
+    wavelength: f32,
+) -> f32 {
+    let n1 = Complex::new(n_outer, 0.);
+    let n2 = Complex::new(ior, 0.);
+    let n3 = Complex::new(eta, k);
+
+    // Snell's law through the layers, n sinθ being the same in each
+    let sin2_1 = (1. - cos_i * cos_i).max(0.);
+    let sin2_2 = sin2_1 * (n_outer / ior).powi(2);
+    if sin2_2 >= 1. {
+        return 1.;
+    }
+    let cos1 = Complex::new(cos_i, 0.);
+    let cos2 = Complex::new((1. - sin2_2).sqrt(), 0.);
+    let s3 = (n1 / n3) * Complex::new(sin2_1.sqrt(), 0.);
+    let cos3 = (Complex::new(1., 0.) - s3 * s3).sqrt();
+
+    let (r12s, r12p) = amplitudes(n1, cos1, n2, cos2);
+    let (r23s, r23p) = amplitudes(n2, cos2, n3, cos3);
+
+    // Round trip through the film
+    let phase = Complex::exp_i(4. * PI * ior * thickness * cos2.re / wavelength);
+    let airy = |r12: Complex, r23: Complex| {
+        let one = Complex::new(1., 0.);
+        ((r12 + r23 * phase) / (one + r12 * r23 * phase)).norm_squared()
+    };
+    (0.5 * (airy(r12s, r23s) + airy(r12p, r23p))).min(1.)
+}
+
+/// Transparent layer a few hundred nanometers thick over a surface. Light reflected at its
+/// top and bottom interferes, tinting reflections by the thickness and the viewing angle
+#[derive(Clone)]
+pub struct ThinFilm {
+    /// Thickness in nanometers, read from the first channel
+    thickness: Arc<dyn Texture>,
+    ior: f32,
+}
+
+impl ThinFilm {
+    pub fn from(thickness: f32, ior: f32) -> Self {
+        Self::from_texture(
+            Arc::new(SolidColor::from(color::from(
+                thickness, thickness, thickness,
+            ))),
+            ior,
+        )
+    }
+    pub fn from_texture(thickness: Arc<dyn Texture>, ior: f32) -> Self {
+        Self { thickness, ior }
+    }
+
+    /// `film_reflectance` for each of the `RGB_WAVELENGTHS`, `substrate` giving the complex
+    /// index of the surface under the film in each channel. Given the hero `wavelength` of a
+    /// spectral path instead, the film is evaluated there alone, with the substrate of the
+    /// nearest channel, and the reflectance returned as a flat color
+    pub fn reflectance(
+        &self,
+        rec: &HitRecord,
+        cos_i: f32,
+        n_outer: f32,
+        substrate: [(f32, f32); 3],
+        wavelength: f32,
+    ) -> color {
+        let thickness = self.thickness.value(rec.u, rec.v, &rec.p).x().max(0.);
+        let at = |i: usize, wavelength: f32| {
+            film_reflectance(
+                self.ior,
+                thickness,
+                cos_i,
+                n_outer,
+                substrate[i],
+                wavelength,
+            )
+        };
+        if wavelength > 0. {
+            let nearest = (0..3)
+                .min_by(|&a, &b| {
+                    let da = (RGB_WAVELENGTHS[a] - wavelength).abs();
+                    let db = (RGB_WAVELENGTHS[b] - wavelength).abs();
+                    da.partial_cmp(&db).unwrap()
+                })
+                .unwrap();
+            let r = at(nearest, wavelength);
+            return color::from(r, r, r);
+        }
+        color::from(
+            at(0, RGB_WAVELENGTHS[0]),
+            at(1, RGB_WAVELENGTHS[1]),
+            at(2, RGB_WAVELENGTHS[2]),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::{Dielectric, Material};
+    use crate::material::microfacet::{fresnel_conductor, fresnel_dielectric};
+    use crate::ray::Ray;
+
+    #[test]
+    fn film_limits() {
+        // A film of zero thickness or of the outer index vanishes
+        for &cos_i in &[0.2, 0.6, 1.] {
+            let r = film_reflectance(1.33, 0., cos_i, 1., (1.5, 0.), 550.);
+            assert!((r - fresnel_dielectric(cos_i, 1.5)).abs() < 1e-4);
+            let r = film_reflectance(1., 300., cos_i, 1., (0.2, 3.9), 550.);
+            assert!((r - fresnel_conductor(cos_i, 0.2, 3.9)).abs() < 1e-4);
+        }
+
+        // Quarter-wave coating at normal incidence: ((n1 n3 - n2²) / (n1 n3 + n2²))²
+        let (n2, n3) = (1.5f32.sqrt(), 1.5);
+        let r = film_reflectance(n2, 550. / (4. * n2), 1., 1., (n3, 0.), 550.);
+        let expected = ((n3 - n2 * n2) / (n3 + n2 * n2)).powi(2);
+        assert!((r - expected).abs() < 1e-5 && r < 1e-4);
+    }
+
+    #[test]
+    fn spectral_paths_see_the_film_at_their_wavelength() {
+        let film = ThinFilm::from(400., 1.33);
+        let glass = Dielectric::new(1.5).with_thin_film(film.clone());
+        assert!(glass.is_dispersive());
+        let r = Ray::new(point3::from(0., 1., 0.), Vec3::from(0., -1., 0.), 0.);
+        let rec = HitRecord::from(&r, 1., Vec3::from(0., 1., 0.), (0., 0.), &glass);
+
+        let substrate = [(1.5, 0.); 3];
+        for &wavelength in &[420., 500., 610., 690.] {
+            let c = film.reflectance(&rec, 0.8, 1., substrate, wavelength);
+            let expected = film_reflectance(1.33, 400., 0.8, 1., (1.5, 0.), wavelength);
+            assert!((c - color::from(expected, expected, expected)).len() < 1e-6);
+        }
+        let rgb = film.reflectance(&rec, 0.8, 1., substrate, 0.);
+        for i in 0..3 {
+            let expected = film_reflectance(1.33, 400., 0.8, 1., (1.5, 0.), RGB_WAVELENGTHS[i]);
+            assert!((rgb[i] - expected).abs() < 1e-6);
+        }
+    }
+}
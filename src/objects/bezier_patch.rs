@@ -0,0 +1,328 @@
+use crate::{
+    aabb::*, material::material::Material, objects::curve::*, objects::hittable::*,
+    objects::mesh::*, onb::*, ray::*, vec3::*,
+};
+use std::fs;
+use std::sync::Arc;
+
+/// Depth of the quadtree of sub-patches whose centers seed the Newton iterations
+const SUBDIVISION_DEPTH: usize = 3;
+const NEWTON_ITERATIONS: usize = 12;
+
+/// Bicubic Bézier patch intersected directly by Newton iteration. Control points are given
+/// row by row, `u` running along the rows and `v` across them
+pub struct BezierPatch {
+    cp: [point3; 16],
+    nodes: Vec<PatchNode>,
+    tolerance: f32,
+    mat_ptr: Arc<dyn Material>,
+}
+
+/// Sub-patch bounded by the hull of its control points, covering `u0..u1` by `v0..v1`
+struct PatchNode {
+    bbox: Aabb,
+    uv: (f32, f32, f32, f32),
+    children: Option<[usize; 4]>,
+}
+
+impl BezierPatch {
+    pub fn from(cp: [point3; 16], m: Arc<dyn Material>) -> Self {
+        let mut patch = Self {
+            cp,
+            nodes: vec![],
+            tolerance: 0.,
+            mat_ptr: m,
+        };
+        patch.build(&cp, (0., 1., 0., 1.), SUBDIVISION_DEPTH);
+        let extent = patch.nodes[0].bbox.max() - patch.nodes[0].bbox.min();
+        patch.tolerance = 1e-5 * (1. + extent.len());
+        patch
+    }
+
+    /// Loads the patches of a `.bpt` file, such as the classic Utah teapot: the patch count,
+    /// then for each patch its degrees followed by its control points. Only bicubic patches
+    /// are kept
+    pub fn from_bpt_file(filename: &str, m: Arc<dyn Material>) -> Vec<Self> {
+        let text = match fs::read_to_string(filename) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("ERROR: Could not read patch file {}: {}", filename, e);
+                return vec![];
+            }
+        };
+        let mut numbers = text.split_whitespace().map(|n| n.parse::<f32>());
+        let mut next = || match numbers.next() {
+            Some(Ok(n)) => Some(n),
+            _ => None,
+        };
+
+        let mut patches = vec![];
+        let count = next().unwrap_or(0.) as usize;
+        for _ in 0..count {
+            let (du, dv) = match (next(), next()) {
+                (Some(du), Some(dv)) => (du as usize, dv as usize),
+                _ => break,
+            };
+            let points: Option<Vec<point3>> = (0..(du + 1) * (dv + 1))
+                .map(|_| Some(point3::from(next()?, next()?, next()?)))
+                .collect();
+            match points {
+                Some(points) if du == 3 && dv == 3 => {
+                    let mut cp = [point3::new(); 16];
+                    cp.copy_from_slice(&points);
+                    patches.push(Self::from(cp, m.clone()));
+                }
+                Some(_) => eprintln!("Skipping patch of degree {}x{} in {}", du, dv, filename),
+                None => {
+                    eprintln!("ERROR: Truncated patch file {}", filename);
+                    break;
+                }
+            }
+        }
+        patches
+    }
+
+    fn build(&mut self, cp: &[point3; 16], uv: (f32, f32, f32, f32), depth: usize) -> usize {
+        let mut min = cp[0];
+        let mut max = cp[0];
+        for p in &cp[1..] {
+            for a in 0..3 {
+                min[a] = min[a].min(p[a]);
+                max[a] = max[a].max(p[a]);
+            }
+        }
+        // Padded so that flat patches still have a volume
+        let pad = Vec3::from(0.0001, 0.0001, 0.0001);
+        let index = self.nodes.len();
+        self.nodes.push(PatchNode {
+            bbox: Aabb::from(&(min - pad), &(max + pad)),
+            uv,
+            children: None,
+        });
+        if depth == 0 {
+            return index;
+        }
+
+        let (u0, u1, v0, v1) = uv;
+        let (um, vm) = (0.5 * (u0 + u1), 0.5 * (v0 + v1));
+        let (left, right) = split_u(cp);
+        let (bottom_left, top_left) = split_v(&left);
+        let (bottom_right, top_right) = split_v(&right);
+        let children = [
+            self.build(&bottom_left, (u0, um, v0, vm), depth - 1),
+            self.build(&bottom_right, (um, u1, v0, vm), depth - 1),
+            self.build(&top_left, (u0, um, vm, v1), depth - 1),
+            self.build(&top_right, (um, u1, vm, v1), depth - 1),
+        ];
+        self.nodes[index].children = Some(children);
+        index
+    }
+
+    pub fn point(&self, u: f32, v: f32) -> point3 {
+        eval_bezier(&column(&self.cp, u), v)
+    }
+    /// Returns the point at `(u, v)` with its derivatives along `u` and `v`
+    pub fn derivatives(&self, u: f32, v: f32) -> (point3, Vec3, Vec3) {
+        let col = column(&self.cp, u);
+        let row_tangents = [
+            bezier_derivative(&row(&self.cp, 0), u),
+            bezier_derivative(&row(&self.cp, 1), u),
+            bezier_derivative(&row(&self.cp, 2), u),
+            bezier_derivative(&row(&self.cp, 3), u),
+        ];
+        (
+            eval_bezier(&col, v),
+            eval_bezier(&row_tangents, v),
+            bezier_derivative(&col, v),
+        )
+    }
+    /// Normal at `(u, v)`, nudged inwards where the patch collapses into a point as at the
+    /// top of the teapot
+    fn normal(&self, u: f32, v: f32) -> Vec3 {
+        let (_, dpdu, dpdv) = self.derivatives(u, v);
+        let n = cross(dpdu, dpdv);
+        if !n.near_zero() {
+            return unit_vector(n);
+        }
+        let nudge = |x: f32| x + if x < 0.5 { 1e-3 } else { -1e-3 };
+        let (_, dpdu, dpdv) = self.derivatives(nudge(u), nudge(v));
+        unit_vector(cross(dpdu, dpdv))
+    }
+
+    /// Samples an `n` by `n` grid of the patch into triangles with analytic normals
+    pub fn tessellate(&self, n: usize) -> Mesh {
+        let n = n.max(1);
+        let mut positions = vec![];
+        let mut normals = vec![];
+        let mut uvs = vec![];
+        for j in 0..=n {
+            for i in 0..=n {
+                let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+                positions.push(self.point(u, v));
+                normals.push(self.normal(u, v));
+                uvs.push((u, v));
+            }
+        }
+        let vertex = |i: usize, j: usize| j * (n + 1) + i;
+        let mut indices = vec![];
+        for j in 0..n {
+            for i in 0..n {
+                let (a, b) = (vertex(i, j), vertex(i + 1, j));
+                let (c, d) = (vertex(i + 1, j + 1), vertex(i, j + 1));
+                indices.push([a, b, c]);
+                indices.push([a, c, d]);
+            }
+        }
+        Mesh::from(positions, indices)
+            .with_normals(normals)
+            .with_uvs(uvs)
+    }
+
+    /// Solves for the `(u, v)` where the patch crosses the two planes `(n1, n2)` that meet
+    /// along the ray, starting from `(u, v)`
+    fn newton(&self, r: &Ray, n1: Vec3, n2: Vec3, mut u: f32, mut v: f32) -> Option<(f32, f32)> {
+        for _ in 0..NEWTON_ITERATIONS {
+            let (p, dpdu, dpdv) = self.derivatives(u, v);
+            let d = p - r.origin();
+            let (f1, f2) = (dot(n1, d), dot(n2, d));
+            if f1.abs() + f2.abs() < self.tolerance {
+                let inside = |x: f32| (-1e-4..=1. + 1e-4).contains(&x);
+                return if inside(u) && inside(v) {
+                    Some((u.clamp(0., 1.), v.clamp(0., 1.)))
+                } else {
+                    None
+                };
+            }
+            let (a, b) = (dot(n1, dpdu), dot(n1, dpdv));
+            let (c, e) = (dot(n2, dpdu), dot(n2, dpdv));
+            let det = a * e - b * c;
+            if det.abs() < 1e-12 {
+                return None;
+            }
+            u -= (e * f1 - b * f2) / det;
+            v -= (a * f2 - c * f1) / det;
+        }
+        None
+    }
+}
+
+impl Hittable for BezierPatch {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let frame = Onb::build_from_w(r.direction());
+        let (n1, n2) = (frame.u(), frame.v());
+        let length_squared = r.direction().len_squared();
+
+        let mut closest: Option<(f32, f32, f32)> = None;
+        let mut stack = vec![0];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let t_far = closest.map_or(t_max, |(t, _, _)| t);
+            if !node.bbox.hit(r, t_min, t_far) {
+                continue;
+            }
+            if let Some(children) = node.children {
+                stack.extend_from_slice(&children);
+                continue;
+            }
+
+            let (u0, u1, v0, v1) = node.uv;
+            if let Some((u, v)) = self.newton(r, n1, n2, 0.5 * (u0 + u1), 0.5 * (v0 + v1)) {
+                let t = dot(self.point(u, v) - r.origin(), r.direction()) / length_squared;
+                if t >= t_min && t <= t_far {
+                    closest = Some((t, u, v));
+                }
+            }
+        }
+
+        let (t, u, v) = closest?;
+        let (_, dpdu, dpdv) = self.derivatives(u, v);
+        Some(
+            HitRecord::from(r, t, self.normal(u, v), (u, v), &*self.mat_ptr)
+                .with_tangents((dpdu, dpdv)),
+        )
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        Some(self.nodes[0].bbox)
+    }
+}
+
+fn row(cp: &[point3; 16], j: usize) -> [point3; 4] {
+    [cp[4 * j], cp[4 * j + 1], cp[4 * j + 2], cp[4 * j + 3]]
+}
+
+/// Control points of the curve across the rows at `u`
+fn column(cp: &[point3; 16], u: f32) -> [point3; 4] {
+    [
+        eval_bezier(&row(cp, 0), u),
+        eval_bezier(&row(cp, 1), u),
+        eval_bezier(&row(cp, 2), u),
+        eval_bezier(&row(cp, 3), u),
+    ]
+}
+
+fn bezier_derivative(cp: &[point3; 4], u: f32) -> Vec3 {
+    let a = cp[1] - cp[0];
+    let b = cp[2] - cp[1];
+    let c = cp[3] - cp[2];
+    (a * ((1. - u) * (1. - u)) + b * (2. * u * (1. - u)) + c * (u * u)) * 3.
+}
+
+fn split_u(cp: &[point3; 16]) -> ([point3; 16], [point3; 16]) {
+    let mut first = [point3::new(); 16];
+    let mut second = [point3::new(); 16];
+    for j in 0..4 {
+        let (a, b) = split_bezier(&row(cp, j));
+        first[4 * j..4 * j + 4].copy_from_slice(&a);
+        second[4 * j..4 * j + 4].copy_from_slice(&b);
+    }
+    (first, second)
+}
+
+fn split_v(cp: &[point3; 16]) -> ([point3; 16], [point3; 16]) {
+    let mut first = [point3::new(); 16];
+    let mut second = [point3::new(); 16];
+    for i in 0..4 {
+        let (a, b) = split_bezier(&[cp[i], cp[4 + i], cp[8 + i], cp[12 + i]]);
+        for j in 0..4 {
+            first[4 * j + i] = a[j];
+            second[4 * j + i] = b[j];
+        }
+    }
+    (first, second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::Lambertian;
+    use crate::objects::mesh::TriangleMesh;
+    use crate::utils::*;
+
+    #[test]
+    fn newton_matches_fine_tessellation() {
+        let mut cp = [point3::new(); 16];
+        for j in 0..4 {
+            for i in 0..4 {
+                let h = if (i + j) % 2 == 0 { 0.6 } else { -0.4 };
+                cp[4 * j + i] = point3::from(i as f32, h, j as f32);
+            }
+        }
+        let m = Arc::new(Lambertian::from(color::new()));
+        let patch = BezierPatch::from(cp, m.clone());
+        let mesh = TriangleMesh::from(patch.tessellate(128), m);
+
+        let mut hits = 0;
+        for _ in 0..300 {
+            let origin = point3::from(random_double(0.2, 2.8), 3., random_double(0.2, 2.8));
+            let r = Ray::new(origin, Vec3::from(random_double(-0.2, 0.2), -1., 0.1), 0.);
+            let exact = patch.hit(&r, 0.001, f32::INFINITY).map(|rec| rec.t);
+            let approx = mesh.hit(&r, 0.001, f32::INFINITY).map(|rec| rec.t);
+            if let (Some(a), Some(b)) = (exact, approx) {
+                assert!((a - b).abs() < 1e-2, "{} against {}", a, b);
+                hits += 1;
+            }
+        }
+        assert!(hits > 250);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::{aabb::*, material::material::Material, objects::hittable::*, onb::*, ray::*, vec3::*};
+use std::f32::consts::SQRT_2;
+use std::sync::Arc;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum CurveType {
+    /// Ribbon always facing the incoming ray, cheap and fine for thin strands
+    Flat,
+    /// Round tube, shaded with a normal that wraps around the width
+    Cylinder,
+}
+
+/// Cubic Bézier strand whose width varies linearly from `width0` to `width1`
+pub struct Curve {
+    cp: [point3; 4],
+    width0: f32,
+    width1: f32,
+    kind: CurveType,
+    max_depth: usize,
+    bbox: Aabb,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl Curve {
+    pub fn from(
+        cp: [point3; 4],
+        width0: f32,
+        width1: f32,
+        kind: CurveType,
+        m: Arc<dyn Material>,
+    ) -> Self {
+        let half_width = 0.5 * width0.max(width1);
+        let mut min = cp[0];
+        let mut max = cp[0];
+        for p in &cp[1..] {
+            for a in 0..3 {
+                min[a] = min[a].min(p[a]);
+                max[a] = max[a].max(p[a]);
+            }
+        }
+        let pad = Vec3::from(half_width, half_width, half_width);
+
+        // Subdivide until the segments deviate from straight lines by a fraction of the width
+        let mut l0: f32 = 0.;
+        for i in 0..2 {
+            l0 = l0.max((cp[i] - cp[i + 1] * 2. + cp[i + 2]).len());
+        }
+        let eps = 0.05 * width0.max(width1).max(1e-6);
+        let r0 = (SQRT_2 * 6. * l0 / (8. * eps)).log2() / 2.;
+        let max_depth = if r0.is_finite() {
+            r0.clamp(0., 10.).round() as usize
+        } else {
+            0
+        };
+
+        Self {
+            cp,
+            width0,
+            width1,
+            kind,
+            max_depth,
+            bbox: Aabb::from(&(min - pad), &(max + pad)),
+            mat_ptr: m,
+        }
+    }
+
+    pub fn point(&self, u: f32) -> point3 {
+        eval_bezier(&self.cp, u)
+    }
+    pub fn tangent(&self, u: f32) -> Vec3 {
+        let cp = &self.cp;
+        let a = cp[1] - cp[0];
+        let b = cp[2] - cp[1];
+        let c = cp[3] - cp[2];
+        (a * ((1. - u) * (1. - u)) + b * (2. * u * (1. - u)) + c * (u * u)) * 3.
+    }
+    fn width(&self, u: f32) -> f32 {
+        self.width0 * (1. - u) + self.width1 * u
+    }
+
+    /// Recursively splits the curve, expressed in a space where the ray starts at the origin
+    /// and travels along +z, and returns the nearest `(z, u, offset)` with `z` between `z_min`
+    /// and `z_max`. `offset` goes from the center line to the ray in the xy plane, in units of
+    /// half the width
+    fn recurse(
+        &self,
+        cp: &[point3; 4],
+        u0: f32,
+        u1: f32,
+        depth: usize,
+        z_min: f32,
+        z_max: f32,
+    ) -> Option<(f32, f32, (f32, f32))> {
+        let half_width = 0.5 * self.width(u0).max(self.width(u1));
+        let mut min = cp[0];
+        let mut max = cp[0];
+        for p in &cp[1..] {
+            for a in 0..3 {
+                min[a] = min[a].min(p[a]);
+                max[a] = max[a].max(p[a]);
+            }
+        }
+        if min.x() - half_width > 0.
+            || max.x() + half_width < 0.
+            || min.y() - half_width > 0.
+            || max.y() + half_width < 0.
+            || max.z() + half_width < z_min
+            || min.z() - half_width > z_max
+        {
+            return None;
+        }
+
+        if depth > 0 {
+            let (first, second) = split_bezier(cp);
+            let u_mid = 0.5 * (u0 + u1);
+            let near = self.recurse(&first, u0, u_mid, depth - 1, z_min, z_max);
+            let z_max = near.map_or(z_max, |(z, _, _)| z);
+            return self
+                .recurse(&second, u_mid, u1, depth - 1, z_min, z_max)
+                .or(near);
+        }
+
+        // Closest point of the segment to the ray, measured in the plane facing the ray
+        let seg = cp[3] - cp[0];
+        let seg_len2 = seg.x() * seg.x() + seg.y() * seg.y();
+        let w = if seg_len2 > 0. {
+            -(cp[0].x() * seg.x() + cp[0].y() * seg.y()) / seg_len2
+        } else {
+            0.
+        };
+        // The strand ends flat across its tangent at both ends
+        if (u0 == 0. && w < 0.) || (u1 == 1. && w > 1.) {
+            return None;
+        }
+        let w = w.clamp(0., 1.);
+        let pc = eval_bezier(cp, w);
+        let u = u0 + (u1 - u0) * w;
+        let hit_width = self.width(u);
+
+        let dist2 = pc.x() * pc.x() + pc.y() * pc.y();
+        if dist2 > 0.25 * hit_width * hit_width || pc.z() < z_min || pc.z() > z_max {
+            return None;
+        }
+
+        let offset = (-pc.x() / (0.5 * hit_width), -pc.y() / (0.5 * hit_width));
+        Some((pc.z(), u, offset))
+    }
+}
+
+pub(crate) fn eval_bezier(cp: &[point3; 4], u: f32) -> point3 {
+    let a = cp[0] * (1. - u) + cp[1] * u;
+    let b = cp[1] * (1. - u) + cp[2] * u;
+    let c = cp[2] * (1. - u) + cp[3] * u;
+    let d = a * (1. - u) + b * u;
+    let e = b * (1. - u) + c * u;
+    d * (1. - u) + e * u
+}
+
+pub(crate) fn split_bezier(cp: &[point3; 4]) -> ([point3; 4], [point3; 4]) {
+    let m01 = (cp[0] + cp[1]) * 0.5;
+    let m12 = (cp[1] + cp[2]) * 0.5;
+    let m23 = (cp[2] + cp[3]) * 0.5;
+    let m012 = (m01 + m12) * 0.5;
+    let m123 = (m12 + m23) * 0.5;
+    let mid = (m012 + m123) * 0.5;
+    ([cp[0], m01, m012, mid], [mid, m123, m23, cp[3]])
+}
+
+/// De Casteljau split of a cubic Bézier at parameter `u`
+pub(crate) fn split_bezier_at(cp: &[point3; 4], u: f32) -> ([point3; 4], [point3; 4]) {
+    let lerp = |a: point3, b: point3| a + (b - a) * u;
+    let (m01, m12, m23) = (lerp(cp[0], cp[1]), lerp(cp[1], cp[2]), lerp(cp[2], cp[3]));
+    let (m012, m123) = (lerp(m01, m12), lerp(m12, m23));
+    let mid = lerp(m012, m123);
+    ([cp[0], m01, m012, mid], [mid, m123, m23, cp[3]])
+}
+
+impl Hittable for Curve {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return None;
+        }
+        let ray_length = r.direction().len();
+        let frame = Onb::build_from_w(r.direction());
+        let to_ray_space = |p: &point3| {
+            let d = *p - r.origin();
+            Vec3::from(dot(d, frame.u()), dot(d, frame.v()), dot(d, frame.w()))
+        };
+        let cp = [
+            to_ray_space(&self.cp[0]),
+            to_ray_space(&self.cp[1]),
+            to_ray_space(&self.cp[2]),
+            to_ray_space(&self.cp[3]),
+        ];
+
+        let (z, u, offset) = self.recurse(
+            &cp,
+            0.,
+            1.,
+            self.max_depth,
+            t_min * ray_length,
+            t_max * ray_length,
+        )?;
+        let t = z / ray_length;
+
+        let dpdu = self.tangent(u);
+        let tangent = unit_vector(dpdu);
+        let towards_ray = frame.w().inv();
+        let perpendicular = towards_ray - tangent * dot(towards_ray, tangent);
+        let facing = if perpendicular.near_zero() {
+            towards_ray
+        } else {
+            unit_vector(perpendicular)
+        };
+        let across = frame.u() * offset.0 + frame.v() * offset.1;
+        let outward_normal = match self.kind {
+            CurveType::Flat => facing,
+            CurveType::Cylinder => across + facing * (1. - across.len_squared()).max(0.).sqrt(),
+        };
+
+        // v runs across the width, from one edge to the other
+        let sideways = cross(tangent, facing);
+        let side = dot(across, sideways);
+        let dpdv = sideways * self.width(u);
+
+        Some(
+            HitRecord::from(r, t, outward_normal, (u, 0.5 * (side + 1.)), &*self.mat_ptr)
+                .with_tangents((dpdu, dpdv)),
+        )
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::Lambertian;
+
+    fn straight(kind: CurveType) -> Curve {
+        // Along x from -1 to 1, narrowing from 0.2 to 0.1
+        Curve::from(
+            [
+                point3::from(-1., 0., 0.),
+                point3::from(-1. / 3., 0., 0.),
+                point3::from(1. / 3., 0., 0.),
+                point3::from(1., 0., 0.),
+            ],
+            0.2,
+            0.1,
+            kind,
+            Arc::new(Lambertian::from(color::new())),
+        )
+    }
+
+    #[test]
+    fn hits_within_the_width_and_between_the_ends() {
+        for &kind in &[CurveType::Flat, CurveType::Cylinder] {
+            let curve = straight(kind);
+            let ray =
+                |x: f32, y: f32| Ray::new(point3::from(x, y, -5.), Vec3::from(0., 0., 1.), 0.);
+
+            let rec = curve.hit(&ray(0., 0.), 0.001, f32::INFINITY).unwrap();
+            assert!((rec.t - 5.).abs() < 1e-3);
+            assert!((rec.u - 0.5).abs() < 1e-3);
+
+            // Width 0.15 at the middle, 0.2 near the start
+            assert!(curve.hit(&ray(0., 0.07), 0.001, f32::INFINITY).is_some());
+            assert!(curve.hit(&ray(0., 0.08), 0.001, f32::INFINITY).is_none());
+            assert!(curve.hit(&ray(-0.95, 0.09), 0.001, f32::INFINITY).is_some());
+
+            // Past the end caps
+            assert!(curve.hit(&ray(-1.02, 0.), 0.001, f32::INFINITY).is_none());
+            assert!(curve.hit(&ray(1.02, 0.), 0.001, f32::INFINITY).is_none());
+        }
+    }
+
+    #[test]
+    fn ray_leaving_the_curve_finds_its_next_crossing() {
+        // U-shaped strand in the xz plane, crossed twice by a ray along x
+        let curve = Curve::from(
+            [
+                point3::from(-1., 0., 0.),
+                point3::from(-1., 0., 2.),
+                point3::from(1., 0., 2.),
+                point3::from(1., 0., 0.),
+            ],
+            0.05,
+            0.05,
+            CurveType::Flat,
+            Arc::new(Lambertian::from(color::new())),
+        );
+        let start = curve.point(0.1);
+        let r = Ray::new(start, Vec3::from(1., 0., 0.), 0.);
+        let rec = curve.hit(&r, 0.001, f32::INFINITY).unwrap();
+        assert!((rec.t + 2. * start.x()).abs() < 1e-2, "{}", rec.t);
+        assert!((rec.u - 0.9).abs() < 1e-2, "{}", rec.u);
+    }
+}
@@ -0,0 +1,375 @@
+use crate::{
+    aabb::*, material::material::Material, objects::hittable::*, objects::mesh::*, ray::*,
+    texture::*, vec3::*,
+};
+use std::sync::Arc;
+
+/// Terrain surface over a regular grid of height samples, traced through a min-max mipmap
+pub struct Heightfield {
+    heights: Vec<f32>,
+    normals: Vec<Vec3>,
+    nx: usize,
+    nz: usize,
+    corner: point3,
+    size: Vec3,
+    // levels[0] holds the height range of every grid cell, each next level merges 2x2 blocks
+    levels: Vec<MinMaxLevel>,
+    bbox: Aabb,
+    mat_ptr: Arc<dyn Material>,
+}
+
+struct MinMaxLevel {
+    width: usize,
+    depth: usize,
+    ranges: Vec<(f32, f32)>,
+}
+
+impl MinMaxLevel {
+    fn range(&self, i: usize, j: usize) -> (f32, f32) {
+        self.ranges[j * self.width + i]
+    }
+}
+
+impl Heightfield {
+    /// `height(u, v)` is sampled on an `nx` by `nz` grid with `u` and `v` in [0, 1], and is
+    /// scaled by `size.y()`. The terrain spans `size.x()` by `size.z()` from `corner`
+    pub fn from_fn<F>(
+        nx: usize,
+        nz: usize,
+        corner: point3,
+        size: Vec3,
+        height: F,
+        m: Arc<dyn Material>,
+    ) -> Self
+    where
+        F: Fn(f32, f32) -> f32,
+    {
+        let nx = nx.max(2);
+        let nz = nz.max(2);
+        let mut heights = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                let u = i as f32 / (nx - 1) as f32;
+                let v = j as f32 / (nz - 1) as f32;
+                heights.push(corner.y() + size.y() * height(u, v));
+            }
+        }
+
+        let mut field = Self {
+            heights,
+            normals: vec![],
+            nx,
+            nz,
+            corner,
+            size,
+            levels: vec![],
+            bbox: Aabb::new(),
+            mat_ptr: m,
+        };
+        field.normals = field.vertex_normals();
+        field.levels = field.min_max_levels();
+
+        let (lo, hi) = field.levels[field.levels.len() - 1].range(0, 0);
+        field.bbox = Aabb::from(
+            &point3::from(corner.x(), lo - 0.0001, corner.z()),
+            &point3::from(corner.x() + size.x(), hi + 0.0001, corner.z() + size.z()),
+        );
+        field
+    }
+    /// Uses the luminance of every texel of a grayscale (or color) image as height
+    pub fn from_image(
+        image: &ImageTexture,
+        corner: point3,
+        size: Vec3,
+        m: Arc<dyn Material>,
+    ) -> Self {
+        let (width, height) = image.dimensions();
+        Self::from_fn(
+            width,
+            height,
+            corner,
+            size,
+            |u, v| {
+                let c = image.value(u, 1. - v, &point3::new());
+                0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+            },
+            m,
+        )
+    }
+
+    fn dx(&self) -> f32 {
+        self.size.x() / (self.nx - 1) as f32
+    }
+    fn dz(&self) -> f32 {
+        self.size.z() / (self.nz - 1) as f32
+    }
+    fn height(&self, i: usize, j: usize) -> f32 {
+        self.heights[j * self.nx + i]
+    }
+    fn vertex(&self, i: usize, j: usize) -> point3 {
+        point3::from(
+            self.corner.x() + i as f32 * self.dx(),
+            self.height(i, j),
+            self.corner.z() + j as f32 * self.dz(),
+        )
+    }
+
+    fn vertex_normals(&self) -> Vec<Vec3> {
+        let mut normals = Vec::with_capacity(self.nx * self.nz);
+        for j in 0..self.nz {
+            for i in 0..self.nx {
+                let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.nx - 1));
+                let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.nz - 1));
+                let slope_x =
+                    (self.height(i1, j) - self.height(i0, j)) / ((i1 - i0) as f32 * self.dx());
+                let slope_z =
+                    (self.height(i, j1) - self.height(i, j0)) / ((j1 - j0) as f32 * self.dz());
+                normals.push(unit_vector(Vec3::from(-slope_x, 1., -slope_z)));
+            }
+        }
+        normals
+    }
+
+    fn min_max_levels(&self) -> Vec<MinMaxLevel> {
+        let mut ranges = Vec::with_capacity((self.nx - 1) * (self.nz - 1));
+        for j in 0..self.nz - 1 {
+            for i in 0..self.nx - 1 {
+                let corners = [
+                    self.height(i, j),
+                    self.height(i + 1, j),
+                    self.height(i, j + 1),
+                    self.height(i + 1, j + 1),
+                ];
+                let lo = corners.iter().cloned().fold(f32::INFINITY, f32::min);
+                let hi = corners.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+                ranges.push((lo, hi));
+            }
+        }
+        let mut levels = vec![MinMaxLevel {
+            width: self.nx - 1,
+            depth: self.nz - 1,
+            ranges,
+        }];
+
+        loop {
+            let prev = &levels[levels.len() - 1];
+            if prev.width == 1 && prev.depth == 1 {
+                break;
+            }
+            let width = prev.width.div_ceil(2);
+            let depth = prev.depth.div_ceil(2);
+            let mut ranges = Vec::with_capacity(width * depth);
+            for j in 0..depth {
+                for i in 0..width {
+                    let mut range = (f32::INFINITY, f32::NEG_INFINITY);
+                    for (ci, cj) in Self::children(i, j, prev.width, prev.depth) {
+                        let (lo, hi) = prev.range(ci, cj);
+                        range = (range.0.min(lo), range.1.max(hi));
+                    }
+                    ranges.push(range);
+                }
+            }
+            levels.push(MinMaxLevel {
+                width,
+                depth,
+                ranges,
+            });
+        }
+        levels
+    }
+
+    fn children(i: usize, j: usize, width: usize, depth: usize) -> Vec<(usize, usize)> {
+        let mut children = Vec::with_capacity(4);
+        for cj in 2 * j..(2 * j + 2).min(depth) {
+            for ci in 2 * i..(2 * i + 2).min(width) {
+                children.push((ci, cj));
+            }
+        }
+        children
+    }
+
+    fn node_box(&self, level: usize, i: usize, j: usize) -> Aabb {
+        let cells = 1 << level;
+        let (lo, hi) = self.levels[level].range(i, j);
+        let x0 = self.corner.x() + (i * cells) as f32 * self.dx();
+        let z0 = self.corner.z() + (j * cells) as f32 * self.dz();
+        let x1 = self.corner.x() + (((i + 1) * cells).min(self.nx - 1)) as f32 * self.dx();
+        let z1 = self.corner.z() + (((j + 1) * cells).min(self.nz - 1)) as f32 * self.dz();
+        Aabb::from(
+            &point3::from(x0, lo - 0.0001, z0),
+            &point3::from(x1, hi + 0.0001, z1),
+        )
+    }
+
+    fn traverse(
+        &self,
+        level: usize,
+        i: usize,
+        j: usize,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord<'_>> {
+        if level == 0 {
+            return self.hit_cell(i, j, r, t_min, t_max);
+        }
+
+        // Children are visited front to back, so a hit ends the walk before the farther ones
+        let below = &self.levels[level - 1];
+        let mut children: Vec<(f32, usize, usize)> = Self::children(i, j, below.width, below.depth)
+            .into_iter()
+            .filter_map(|(ci, cj)| {
+                let (enter, _) = self
+                    .node_box(level - 1, ci, cj)
+                    .hit_interval(r, t_min, t_max)?;
+                Some((enter, ci, cj))
+            })
+            .collect();
+        children.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut closest: Option<HitRecord> = None;
+        let mut closest_so_far = t_max;
+        for (enter, ci, cj) in children {
+            if enter >= closest_so_far {
+                break;
+            }
+            if let Some(rec) = self.traverse(level - 1, ci, cj, r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                closest = Some(rec);
+            }
+        }
+        closest
+    }
+
+    fn hit_cell(
+        &self,
+        i: usize,
+        j: usize,
+        r: &Ray,
+        t_min: f32,
+        t_max: f32,
+    ) -> Option<HitRecord<'_>> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut closest: Option<HitRecord> = None;
+        let mut closest_so_far = t_max;
+
+        for triangle in &[[0, 1, 2], [0, 2, 3]] {
+            let [a, b, c] = [
+                corners[triangle[0]],
+                corners[triangle[1]],
+                corners[triangle[2]],
+            ];
+            let (p0, p1, p2) = (
+                self.vertex(a.0, a.1),
+                self.vertex(b.0, b.1),
+                self.vertex(c.0, c.1),
+            );
+            let (t, b1, b2) = match hit_triangle(r, &p0, &p1, &p2, t_min, closest_so_far) {
+                Some(hit) => hit,
+                None => continue,
+            };
+            let b0 = 1. - b1 - b2;
+            let normal = |(ni, nj): (usize, usize)| self.normals[nj * self.nx + ni];
+            let shading_normal = unit_vector(normal(a) * b0 + normal(b) * b1 + normal(c) * b2);
+
+            let uv_of = |p: &point3| {
+                (
+                    (p.x() - self.corner.x()) / self.size.x(),
+                    (p.z() - self.corner.z()) / self.size.z(),
+                )
+            };
+            let tangents = triangle_tangents(&[p0, p1, p2], &[uv_of(&p0), uv_of(&p1), uv_of(&p2)])
+                .unwrap_or((
+                    Vec3::from(self.size.x(), 0., 0.),
+                    Vec3::from(0., 0., self.size.z()),
+                ));
+
+            closest_so_far = t;
+            closest = Some(
+                HitRecord::from(r, t, shading_normal, uv_of(&r.at(t)), &*self.mat_ptr)
+                    .with_tangents(along_surface(tangents, shading_normal)),
+            );
+        }
+        closest
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let root = self.levels.len() - 1;
+        if !self.node_box(root, 0, 0).hit(r, t_min, t_max) {
+            return None;
+        }
+        self.traverse(root, 0, 0, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::material::Lambertian, utils::*};
+
+    /// Closest hit found by testing every cell of the grid
+    fn brute_force(field: &Heightfield, r: &Ray) -> Option<f32> {
+        let mut closest: Option<f32> = None;
+        for j in 0..field.nz - 1 {
+            for i in 0..field.nx - 1 {
+                let t_max = closest.unwrap_or(f32::INFINITY);
+                if let Some(rec) = field.hit_cell(i, j, r, 0.001, t_max) {
+                    closest = Some(rec.t);
+                }
+            }
+        }
+        closest
+    }
+
+    #[test]
+    fn mipmap_traversal_matches_brute_force() {
+        let heights: Vec<f32> = (0..13 * 11).map(|_| random_double(0., 1.)).collect();
+        let field = Heightfield::from_fn(
+            13,
+            11,
+            point3::from(-1., 0., -1.),
+            Vec3::from(2., 0.5, 2.),
+            |u, v| heights[(v * 10.).round() as usize * 13 + (u * 12.).round() as usize],
+            Arc::new(Lambertian::from(color::new())),
+        );
+
+        let mut rays = vec![];
+        for _ in 0..2000 {
+            let origin = point3::from(
+                random_double(-2., 2.),
+                random_double(0.6, 2.),
+                random_double(-2., 2.),
+            );
+            let target = point3::from(random_double(-1., 1.), 0., random_double(-1., 1.));
+            rays.push(Ray::new(origin, target - origin, 0.));
+        }
+        // Horizontal rays grazing the highest point of a cell
+        for j in 0..10 {
+            for i in 0..12 {
+                let (_, hi) = field.levels[0].range(i, j);
+                let z = -1. + (j as f32 + 0.5) * field.dz();
+                rays.push(Ray::new(
+                    point3::from(-2., hi, z),
+                    Vec3::from(1., 0., 0.01),
+                    0.,
+                ));
+            }
+        }
+
+        for r in &rays {
+            let expected = brute_force(&field, r);
+            let found = field.hit(r, 0.001, f32::INFINITY).map(|rec| rec.t);
+            match (expected, found) {
+                (None, None) => {}
+                (Some(a), Some(b)) => assert!((a - b).abs() < 1e-4, "{} {}", a, b),
+                _ => panic!("{:?} {:?}", expected, found),
+            }
+        }
+    }
+}
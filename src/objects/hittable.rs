@@ -1,6 +1,6 @@
-use crate::{aabb::*, material::material::*, ray::*, utils::*, vec3::*};
-use lazy_static::*;
+use crate::{aabb::*, material::material::*, onb::*, ray::*, utils::*, vec3::*};
 use std::f32::INFINITY as inf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 #[derive(Clone, Copy)]
@@ -11,26 +11,43 @@ pub struct HitRecord<'a> {
     pub t: f32,
     pub u: f32,
     pub v: f32,
+    /// Derivatives of the hit point along `u` and `v`. Primitives without a parameterization
+    /// get an arbitrary frame around the normal, media leave them zero
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front_face: bool,
 }
 
-lazy_static! {
-    static ref DFL_LAMBERTIAN: Lambertian = Lambertian::from(color::new());
-}
-
-impl HitRecord<'_> {
-    pub fn void() -> Self {
-        Self {
-            p: Vec3::new(),
-            normal: Vec3::new(),
-            mat_ptr: &*DFL_LAMBERTIAN,
-            t: 0.,
-            u: 0.,
-            v: 0.,
+impl<'a> HitRecord<'a> {
+    /// Builds the record of `r` hitting a surface at `t`, orienting the normal against the ray
+    pub fn from(
+        r: &Ray,
+        t: f32,
+        outward_normal: Vec3,
+        (u, v): (f32, f32),
+        mat_ptr: &'a dyn Material,
+    ) -> Self {
+        let frame = Onb::build_from_w(outward_normal);
+        let mut rec = Self {
+            p: r.at(t),
+            normal: outward_normal,
+            mat_ptr,
+            t,
+            u,
+            v,
+            dpdu: frame.u(),
+            dpdv: frame.v(),
             front_face: true,
-        }
+        };
+        rec.set_face_normal(r, outward_normal);
+        rec
+    }
+    pub fn with_tangents(mut self, (dpdu, dpdv): (Vec3, Vec3)) -> Self {
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self
     }
-    pub fn set_face_normal(&mut self, r: Ray, outward_normal: Vec3) {
+    pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
         self.front_face = dot(r.direction(), outward_normal) < 0.;
         self.normal = if self.front_face {
             outward_normal
@@ -41,12 +58,41 @@ impl HitRecord<'_> {
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
-        true
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>>;
+    /// Box enclosing the object over the `time0..time1` interval, `None` if it is unbounded
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<Aabb>;
+}
+
+/// Debug mode in which containers check that every hit lies within the box of the object hit.
+/// Off unless enabled, as it slows rendering down and aborts on the first loose box
+static VALIDATE_BOUNDS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_bounds_validation(enabled: bool) {
+    VALIDATE_BOUNDS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the hit `rec` of `r` on `object` lies within the box `object` reports
+pub fn hit_within_bounds(object: &dyn Hittable, r: &Ray, rec: &HitRecord) -> bool {
+    match object.bounding_box(r.time(), r.time()) {
+        Some(bbox) => {
+            let tolerance = 1e-4 * (1. + rec.p.x().abs().max(rec.p.y().abs()).max(rec.p.z().abs()));
+            bbox.contains(&rec.p, tolerance)
+        }
+        None => true,
     }
-    fn bounding_box<'a>(&'a self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-        true
+}
+
+pub fn validate_hit(object: &dyn Hittable, r: &Ray, rec: &HitRecord) {
+    if !VALIDATE_BOUNDS.load(Ordering::Relaxed) {
+        return;
     }
+    assert!(
+        hit_within_bounds(object, r, rec),
+        "hit point {:?} at t={} lies outside of the object box {:?}",
+        rec.p,
+        rec.t,
+        object.bounding_box(r.time(), r.time())
+    );
 }
 
 pub struct Translate {
@@ -63,27 +109,21 @@ impl Translate {
 }
 
 impl Hittable for Translate {
-    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let moved_r = Ray::new(r.origin() - self.offset, r.direction(), r.time());
-        if !self.ptr.hit(&moved_r, t_min, t_max, rec) {
-            return false;
-        }
+        let mut rec = self.ptr.hit(&moved_r, t_min, t_max)?;
 
+        // The direction is unchanged, so the oriented normal still holds
         rec.p = rec.p + self.offset;
-        rec.set_face_normal(moved_r, rec.normal);
-        true
+        Some(rec)
     }
 
-    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-        if !self.ptr.bounding_box(time0, time1, output_box) {
-            return false;
-        }
-        *output_box = Aabb::from(
-            &(output_box.min() + self.offset),
-            &(output_box.max() + self.offset),
-        );
-
-        true
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<Aabb> {
+        let inner = self.ptr.bounding_box(time0, time1)?;
+        Some(Aabb::from(
+            &(inner.min() + self.offset),
+            &(inner.max() + self.offset),
+        ))
     }
 }
 
@@ -91,8 +131,7 @@ pub struct RotateY {
     ptr: Arc<dyn Hittable>,
     sin_theta: f32,
     cos_theta: f32,
-    has_box: bool,
-    bbox: Aabb,
+    bbox: Option<Aabb>,
 }
 
 impl RotateY {
@@ -101,47 +140,45 @@ impl RotateY {
         let sin_theta = radians.sin();
         let ptr = p.clone();
         let cos_theta = radians.cos();
-        let mut bbox = Aabb::new();
-        let has_box = p.bounding_box(0., 1., &mut bbox);
-
-        let mut min = point3::from(inf, inf, inf);
-        let mut max = point3::from(-inf, -inf, -inf);
-
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let x = i as f32 * bbox.max().x() + (1. - i as f32) * bbox.min().x();
-                    let y = i as f32 * bbox.max().y() + (1. - j as f32) * bbox.min().y();
-                    let z = i as f32 * bbox.max().z() + (1. - k as f32) * bbox.min().z();
-
-                    let newx = cos_theta * x + sin_theta * z;
-                    let newz = -sin_theta * x + cos_theta * z;
-
-                    let tester = Vec3::from(newx, y, newz);
-                    for c in 0..3 {
-                        min[c] = min[c].min(tester[c]);
-                        max[c] = max[c].max(tester[c]);
+
+        let bbox = p.bounding_box(0., 1.).map(|bbox| {
+            let mut min = point3::from(inf, inf, inf);
+            let mut max = point3::from(-inf, -inf, -inf);
+
+            for i in 0..2 {
+                for j in 0..2 {
+                    for k in 0..2 {
+                        let x = i as f32 * bbox.max().x() + (1. - i as f32) * bbox.min().x();
+                        let y = j as f32 * bbox.max().y() + (1. - j as f32) * bbox.min().y();
+                        let z = k as f32 * bbox.max().z() + (1. - k as f32) * bbox.min().z();
+
+                        let newx = cos_theta * x + sin_theta * z;
+                        let newz = -sin_theta * x + cos_theta * z;
+
+                        let tester = Vec3::from(newx, y, newz);
+                        for c in 0..3 {
+                            min[c] = min[c].min(tester[c]);
+                            max[c] = max[c].max(tester[c]);
+                        }
                     }
                 }
             }
-        }
-        bbox = Aabb::from(&min, &max);
+            Aabb::from(&min, &max)
+        });
         Self {
             ptr,
             sin_theta,
             cos_theta,
-            has_box,
             bbox,
         }
     }
 }
 
 impl Hittable for RotateY {
-    fn bounding_box(&self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-        *output_box = self.bbox;
-        self.has_box
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        self.bbox
     }
-    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let mut origin = r.origin();
         let mut direction = r.direction();
 
@@ -153,9 +190,7 @@ impl Hittable for RotateY {
 
         let rotated_r = Ray::new(origin, direction, r.time());
 
-        if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
-            return false;
-        }
+        let mut rec = self.ptr.hit(&rotated_r, t_min, t_max)?;
 
         let mut p = rec.p;
         let mut normal = rec.normal;
@@ -166,10 +201,247 @@ impl Hittable for RotateY {
         normal[0] = self.cos_theta * rec.normal[0] + self.sin_theta * rec.normal[2];
         normal[2] = -self.sin_theta * rec.normal[0] + self.cos_theta * rec.normal[2];
 
+        let rotate = |v: Vec3| {
+            Vec3::from(
+                self.cos_theta * v[0] + self.sin_theta * v[2],
+                v[1],
+                -self.sin_theta * v[0] + self.cos_theta * v[2],
+            )
+        };
+
+        // Rotating both the ray and the normal keeps them on the same side
         rec.p = p;
+        rec.normal = normal;
+        rec.dpdu = rotate(rec.dpdu);
+        rec.dpdv = rotate(rec.dpdv);
 
-        rec.set_face_normal(rotated_r, normal);
+        Some(rec)
+    }
+}
 
-        true
+/// Shared object placed with a rotation, uniform scale and offset. The object's local +y axis
+/// is turned towards `up`, then spun by `angle` degrees around it
+pub struct Instance {
+    ptr: Arc<dyn Hittable>,
+    axes: [Vec3; 3],
+    scale: f32,
+    offset: Vec3,
+    bbox: Option<Aabb>,
+}
+
+impl Instance {
+    pub fn from(p: Arc<dyn Hittable>, up: Vec3, angle: f32, scale: f32, offset: Vec3) -> Self {
+        let mut instance = Self {
+            ptr: p,
+            axes: [
+                Vec3::from(1., 0., 0.),
+                Vec3::from(0., 1., 0.),
+                Vec3::from(0., 0., 1.),
+            ],
+            scale: 1.,
+            offset: Vec3::new(),
+            bbox: None,
+        };
+        instance.set_placement(up, angle, scale, offset);
+        instance
+    }
+    /// Moves the instance without touching the shared object, for instance between frames.
+    /// `scale` must be positive
+    pub fn set_placement(&mut self, up: Vec3, angle: f32, scale: f32, offset: Vec3) {
+        let scale = if scale > 0. {
+            scale
+        } else {
+            eprintln!("ERROR: instance scale must be positive, got {}", scale);
+            1.
+        };
+        let y = unit_vector(up);
+        let hint = if y.x().abs() > 0.9 {
+            Vec3::from(0., 0., 1.)
+        } else {
+            Vec3::from(1., 0., 0.)
+        };
+        let x0 = unit_vector(cross(y, hint));
+        let z0 = cross(x0, y);
+        let (sin_theta, cos_theta) = degrees_to_radians(angle).sin_cos();
+        let x = x0 * cos_theta - z0 * sin_theta;
+        let z = x0 * sin_theta + z0 * cos_theta;
+
+        self.axes = [x, y, z];
+        self.scale = scale;
+        self.offset = offset;
+        self.bbox = self.ptr.bounding_box(0., 1.).map(|bbox| {
+            let mut min = point3::from(inf, inf, inf);
+            let mut max = point3::from(-inf, -inf, -inf);
+            for i in 0..8 {
+                let corner = point3::from(
+                    if i & 1 == 0 {
+                        bbox.min().x()
+                    } else {
+                        bbox.max().x()
+                    },
+                    if i & 2 == 0 {
+                        bbox.min().y()
+                    } else {
+                        bbox.max().y()
+                    },
+                    if i & 4 == 0 {
+                        bbox.min().z()
+                    } else {
+                        bbox.max().z()
+                    },
+                );
+                let tester = self.to_world(corner) + self.offset;
+                for c in 0..3 {
+                    min[c] = min[c].min(tester[c]);
+                    max[c] = max[c].max(tester[c]);
+                }
+            }
+            Aabb::from(&min, &max)
+        });
+    }
+    fn to_world(&self, v: Vec3) -> Vec3 {
+        (self.axes[0] * v.x() + self.axes[1] * v.y() + self.axes[2] * v.z()) * self.scale
+    }
+    fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::from(
+            dot(v, self.axes[0]),
+            dot(v, self.axes[1]),
+            dot(v, self.axes[2]),
+        ) / self.scale
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        // Scaling the direction along with the origin keeps t the same in both spaces
+        let local_r = Ray::new(
+            self.to_local(r.origin() - self.offset),
+            self.to_local(r.direction()),
+            r.time(),
+        );
+        let mut rec = self.ptr.hit(&local_r, t_min, t_max)?;
+
+        rec.p = self.to_world(rec.p) + self.offset;
+        rec.normal = self.to_world(rec.normal) / self.scale;
+        rec.dpdu = self.to_world(rec.dpdu);
+        rec.dpdv = self.to_world(rec.dpdv);
+        Some(rec)
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        aarect::*,
+        material::boxx::Boxx,
+        objects::{mesh::*, sphere::Sphere},
+    };
+
+    fn matte() -> Arc<dyn Material> {
+        Arc::new(Lambertian::from(color::new()))
+    }
+
+    /// Sphere reporting a box half its size
+    struct TooSmall(Sphere);
+
+    impl Hittable for TooSmall {
+        fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+            self.0.hit(r, t_min, t_max)
+        }
+        fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+            Some(Aabb::from(
+                &point3::from(-0.5, -0.5, -0.5),
+                &point3::from(0.5, 0.5, 0.5),
+            ))
+        }
+    }
+
+    #[test]
+    fn validation_reports_boxes_too_small() {
+        let r = Ray::new(point3::from(0., 0., -5.), Vec3::from(0., 0., 1.), 0.);
+        let sphere = Sphere::new(point3::new(), 1., matte());
+        let rec = sphere.hit(&r, 0.001, inf).unwrap();
+        assert!(hit_within_bounds(&sphere, &r, &rec));
+
+        let too_small = TooSmall(Sphere::new(point3::new(), 1., matte()));
+        let rec = too_small.hit(&r, 0.001, inf).unwrap();
+        assert!(!hit_within_bounds(&too_small, &r, &rec));
+    }
+
+    #[test]
+    fn rotated_box_encloses_every_corner() {
+        let boxx = Arc::new(Boxx::from(
+            &point3::new(),
+            &point3::from(1., 2., 3.),
+            matte(),
+        ));
+        // A quarter turn takes x to -z and z to x
+        let bbox = RotateY::from(boxx, 90.).bounding_box(0., 1.).unwrap();
+        let expected_min = point3::from(0., 0., -1.);
+        let expected_max = point3::from(3., 2., 0.);
+        assert!((bbox.min() - expected_min).len() < 1e-5, "{:?}", bbox);
+        assert!((bbox.max() - expected_max).len() < 1e-5, "{:?}", bbox);
+    }
+
+    #[test]
+    fn tangents_follow_the_surface() {
+        let objects: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(Sphere::new(point3::from(0., 0., 1.), 2., matte())),
+            Arc::new(XyRect::from(-2., 3., -1., 2., 0.5, matte())),
+            Arc::new(XzRect::from(-2., 3., -1., 2., 0.5, matte())),
+            Arc::new(YzRect::from(-2., 3., -1., 2., 0.5, matte())),
+            Arc::new(TriangleMesh::from(
+                Mesh::uv_sphere(point3::new(), 2., 32, 16),
+                matte(),
+            )),
+        ];
+        let origin = point3::from(4., 5., 6.);
+        let target = point3::from(0.3, 0.4, 0.2);
+        for object in &objects {
+            let r = Ray::new(origin, target - origin, 0.);
+            let rec = object.hit(&r, 0.001, inf).unwrap();
+            assert!(dot(rec.dpdu, rec.normal).abs() < 1e-3 * rec.dpdu.len());
+            assert!(dot(rec.dpdv, rec.normal).abs() < 1e-3 * rec.dpdv.len());
+
+            // A nearby hit moves by dP/du du + dP/dv dv, within the plane of the shading normal
+            let nearby = Ray::new(
+                origin,
+                target + Vec3::from(0.002, -0.001, 0.0015) - origin,
+                0.,
+            );
+            let rec2 = object.hit(&nearby, 0.001, inf).unwrap();
+            let predicted = rec.dpdu * (rec2.u - rec.u) + rec.dpdv * (rec2.v - rec.v);
+            let moved = rec2.p - rec.p;
+            let moved = moved - rec.normal * dot(moved, rec.normal);
+            assert!(
+                (predicted - moved).len() < 0.05 * moved.len(),
+                "{:?} {:?}",
+                predicted,
+                moved
+            );
+        }
+    }
+
+    #[test]
+    fn instance_rejects_non_positive_scales() {
+        let sphere = Arc::new(Sphere::new(point3::new(), 1., matte()));
+        let r = Ray::new(point3::from(0., 0., -5.), Vec3::from(0., 0., 1.), 0.);
+        for &scale in &[0., -2.] {
+            let instance = Instance::from(
+                sphere.clone(),
+                Vec3::from(0., 1., 0.),
+                0.,
+                scale,
+                Vec3::new(),
+            );
+            let rec = instance.hit(&r, 0.001, inf).unwrap();
+            assert!((rec.t - 4.).abs() < 1e-4);
+            assert!(rec.normal.len().is_finite());
+        }
     }
 }
@@ -20,38 +20,29 @@ impl HittableList {
 }
 
 impl Hittable for HittableList {
-    fn bounding_box<'a>(&'a self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-        if self.objects.is_empty() {
-            return false;
-        }
-        let mut temp_box = Aabb::new();
-        let mut first_box = true;
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<Aabb> {
+        let mut output_box: Option<Aabb> = None;
 
         for object in &self.objects {
-            if !object.bounding_box(time0, time1, &mut temp_box) {
-                return false;
-            }
-            *output_box = if first_box {
-                temp_box
-            } else {
-                surrounding_box(&output_box, &temp_box)
+            let temp_box = object.bounding_box(time0, time1)?;
+            output_box = match output_box {
+                Some(b) => Some(surrounding_box(&b, &temp_box)),
+                None => Some(temp_box),
             };
-            first_box = false;
         }
-        true
+        output_box
     }
-    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
-        let mut temp_rec = HitRecord::void();
-        let mut hit_anything = false;
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mut closest: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
 
         for object in &self.objects {
-            if object.hit(r, t_min, closest_so_far, &mut temp_rec) {
-                hit_anything = true;
-                closest_so_far = temp_rec.t;
-                *rec = temp_rec;
+            if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                validate_hit(&**object, r, &rec);
+                closest_so_far = rec.t;
+                closest = Some(rec);
             }
         }
-        hit_anything
+        closest
     }
 }
@@ -0,0 +1,334 @@
+use crate::{
+    aabb::*, bvh::*, material::material::Material, objects::hittable::*, objects::hittable_list::*,
+    onb::*, ray::*, texture::*, vec3::*,
+};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+/// Indexed triangle geometry, shared by all the triangles built from it
+#[derive(Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<point3>,
+    /// Per-vertex normals, empty for flat shading
+    pub normals: Vec<Vec3>,
+    /// Per-vertex texture coordinates, empty to use the barycentric coordinates
+    pub uvs: Vec<(f32, f32)>,
+    pub indices: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    pub fn from(positions: Vec<point3>, indices: Vec<[usize; 3]>) -> Self {
+        Self {
+            positions,
+            normals: vec![],
+            uvs: vec![],
+            indices,
+        }
+    }
+    pub fn with_uvs(mut self, uvs: Vec<(f32, f32)>) -> Self {
+        self.uvs = uvs;
+        self
+    }
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> Self {
+        self.normals = normals;
+        self
+    }
+
+    /// Adds the faces of `other`. Normals and uvs are dropped unless both meshes have them
+    pub fn append(&mut self, other: &Mesh) {
+        if self.positions.is_empty() {
+            *self = other.clone();
+            return;
+        }
+        let offset = self.positions.len();
+        if self.normals.is_empty() || other.normals.is_empty() {
+            self.normals.clear();
+        } else {
+            self.normals.extend_from_slice(&other.normals);
+        }
+        if self.uvs.is_empty() || other.uvs.is_empty() {
+            self.uvs.clear();
+        } else {
+            self.uvs.extend_from_slice(&other.uvs);
+        }
+        self.positions.extend_from_slice(&other.positions);
+        self.indices.extend(
+            other
+                .indices
+                .iter()
+                .map(|[a, b, c]| [a + offset, b + offset, c + offset]),
+        );
+    }
+
+    /// Sphere tessellated along the `get_sphere_uv` parameterization, with `nu` segments
+    /// around and `nv` from pole to pole
+    pub fn uv_sphere(center: point3, radius: f32, nu: usize, nv: usize) -> Self {
+        let (nu, nv) = (nu.max(3), nv.max(2));
+        let mut positions = vec![];
+        let mut normals = vec![];
+        let mut uvs = vec![];
+        for j in 0..=nv {
+            for i in 0..=nu {
+                let (u, v) = (i as f32 / nu as f32, j as f32 / nv as f32);
+                let (phi, theta) = (2. * PI * u, PI * v);
+                let n = Vec3::from(
+                    -theta.sin() * phi.cos(),
+                    -theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                positions.push(center + n * radius);
+                normals.push(n);
+                uvs.push((u, v));
+            }
+        }
+
+        // Quads next to the poles collapse into a single triangle
+        let vertex = |i: usize, j: usize| j * (nu + 1) + i;
+        let mut indices = vec![];
+        for j in 0..nv {
+            for i in 0..nu {
+                let (a, b) = (vertex(i, j), vertex(i + 1, j));
+                let (c, d) = (vertex(i + 1, j + 1), vertex(i, j + 1));
+                if j != 0 {
+                    indices.push([a, b, c]);
+                }
+                if j != nv - 1 {
+                    indices.push([a, c, d]);
+                }
+            }
+        }
+        Self::from(positions, indices)
+            .with_normals(normals)
+            .with_uvs(uvs)
+    }
+
+    /// Replaces the normals by the area weighted average of the faces around each vertex.
+    /// Vertices sharing a position, as along uv seams, are smoothed together
+    pub fn smooth_normals(mut self) -> Self {
+        let key = |p: &point3| (p.x().to_bits(), p.y().to_bits(), p.z().to_bits());
+        let mut sums: HashMap<(u32, u32, u32), Vec3> = HashMap::new();
+        for [i0, i1, i2] in &self.indices {
+            let (p0, p1, p2) = (
+                self.positions[*i0],
+                self.positions[*i1],
+                self.positions[*i2],
+            );
+            let face = cross(p1 - p0, p2 - p0);
+            for p in &[p0, p1, p2] {
+                let sum = sums.entry(key(p)).or_default();
+                *sum = *sum + face;
+            }
+        }
+        self.normals = self
+            .positions
+            .iter()
+            .map(|p| match sums.get(&key(p)) {
+                Some(sum) if !sum.near_zero() => unit_vector(*sum),
+                _ => Vec3::from(0., 1., 0.),
+            })
+            .collect();
+        self
+    }
+
+    /// Moves every vertex along its normal by `scale` times the texture's average channel.
+    /// The detail is only as fine as the tessellation, so refine coarse meshes first
+    pub fn displace(&self, height: &dyn Texture, scale: f32) -> Self {
+        let base = if self.normals.is_empty() {
+            self.clone().smooth_normals()
+        } else {
+            self.clone()
+        };
+        let positions = base
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let (u, v) = base.uvs.get(i).copied().unwrap_or((0., 0.));
+                let h = height.value(u, v, p);
+                *p + base.normals[i] * (scale * (h.x() + h.y() + h.z()) / 3.)
+            })
+            .collect();
+        Self { positions, ..base }.smooth_normals()
+    }
+}
+
+/// Triangle mesh traced through a BVH over its faces
+pub struct TriangleMesh {
+    faces: Arc<dyn Hittable>,
+}
+
+impl TriangleMesh {
+    pub fn from(mesh: Mesh, m: Arc<dyn Material>) -> Self {
+        let mesh = Arc::new(mesh);
+        let mut list = HittableList::new();
+        for index in 0..mesh.indices.len() {
+            list.add(Arc::new(Triangle {
+                mesh: mesh.clone(),
+                index,
+                mat_ptr: m.clone(),
+            }));
+        }
+        if list.objects.is_empty() {
+            eprintln!("TriangleMesh built from a mesh without faces");
+            return Self {
+                faces: Arc::new(list),
+            };
+        }
+        Self {
+            faces: Arc::new(BvhNode::from(&mut list, 0., 1.)),
+        }
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.faces.hit(r, t_min, t_max)
+    }
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<Aabb> {
+        self.faces.bounding_box(time0, time1)
+    }
+}
+
+struct Triangle {
+    mesh: Arc<Mesh>,
+    index: usize,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let mesh = &*self.mesh;
+        let [i0, i1, i2] = mesh.indices[self.index];
+        let p = [mesh.positions[i0], mesh.positions[i1], mesh.positions[i2]];
+        let (t, b1, b2) = hit_triangle(r, &p[0], &p[1], &p[2], t_min, t_max)?;
+        let b0 = 1. - b1 - b2;
+
+        let uv = if mesh.uvs.is_empty() {
+            [(0., 0.), (1., 0.), (1., 1.)]
+        } else {
+            [mesh.uvs[i0], mesh.uvs[i1], mesh.uvs[i2]]
+        };
+        let u = b0 * uv[0].0 + b1 * uv[1].0 + b2 * uv[2].0;
+        let v = b0 * uv[0].1 + b1 * uv[1].1 + b2 * uv[2].1;
+
+        let geometric_normal = unit_vector(cross(p[1] - p[0], p[2] - p[0]));
+        let outward_normal = if mesh.normals.is_empty() {
+            geometric_normal
+        } else {
+            let n = &mesh.normals;
+            unit_vector(n[i0] * b0 + n[i1] * b1 + n[i2] * b2)
+        };
+        let tangents = triangle_tangents(&p, &uv).unwrap_or_else(|| {
+            let frame = Onb::build_from_w(geometric_normal);
+            (frame.u(), frame.v())
+        });
+        let tangents = along_surface(tangents, outward_normal);
+
+        Some(HitRecord::from(r, t, outward_normal, (u, v), &*self.mat_ptr).with_tangents(tangents))
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        let (p0, p1, p2) = (
+            self.mesh.positions[i0],
+            self.mesh.positions[i1],
+            self.mesh.positions[i2],
+        );
+        let mut min = p0;
+        let mut max = p0;
+        for p in &[p1, p2] {
+            for a in 0..3 {
+                min[a] = min[a].min(p[a]);
+                max[a] = max[a].max(p[a]);
+            }
+        }
+        // Padded so that triangles lying in an axis plane still have a volume
+        let pad = Vec3::from(0.0001, 0.0001, 0.0001);
+        Some(Aabb::from(&(min - pad), &(max + pad)))
+    }
+}
+
+/// Möller–Trumbore intersection, returning the ray parameter and the barycentric weights of
+/// `p1` and `p2`
+pub(crate) fn hit_triangle(
+    r: &Ray,
+    p0: &point3,
+    p1: &point3,
+    p2: &point3,
+    t_min: f32,
+    t_max: f32,
+) -> Option<(f32, f32, f32)> {
+    let edge1 = *p1 - *p0;
+    let edge2 = *p2 - *p0;
+    let h = cross(r.direction(), edge2);
+    let det = dot(edge1, h);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1. / det;
+    let s = r.origin() - *p0;
+    let b1 = inv_det * dot(s, h);
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+    let q = cross(s, edge1);
+    let b2 = inv_det * dot(r.direction(), q);
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+    let t = inv_det * dot(edge2, q);
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// Projects the tangents of a flat face onto the plane of an interpolated shading `normal`, so
+/// they stay orthogonal to the normal the materials see
+pub(crate) fn along_surface((dpdu, dpdv): (Vec3, Vec3), normal: Vec3) -> (Vec3, Vec3) {
+    (
+        dpdu - normal * dot(dpdu, normal),
+        dpdv - normal * dot(dpdv, normal),
+    )
+}
+
+/// Solves for the `(dpdu, dpdv)` of the plane through a triangle from its vertex uvs, `None`
+/// when the uvs are degenerate
+pub(crate) fn triangle_tangents(p: &[point3; 3], uv: &[(f32, f32); 3]) -> Option<(Vec3, Vec3)> {
+    let (du02, dv02) = (uv[0].0 - uv[2].0, uv[0].1 - uv[2].1);
+    let (du12, dv12) = (uv[1].0 - uv[2].0, uv[1].1 - uv[2].1);
+    let (dp02, dp12) = (p[0] - p[2], p[1] - p[2]);
+    let det = du02 * dv12 - dv02 * du12;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1. / det;
+    Some((
+        (dp02 * dv12 - dp12 * dv02) * inv_det,
+        (dp12 * du02 - dp02 * du12) * inv_det,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displacement_moves_vertices_along_their_normals() {
+        let sphere = Mesh::uv_sphere(point3::from(1., 0., 0.), 1., 16, 8);
+        let flat = sphere.displace(&SolidColor::from(color::new()), 0.5);
+        let raised = sphere.displace(&SolidColor::from(color::from(1., 1., 1.)), 0.5);
+        for i in 0..sphere.positions.len() {
+            assert!((flat.positions[i] - sphere.positions[i]).len() < 1e-6);
+            let radius = (raised.positions[i] - point3::from(1., 0., 0.)).len();
+            assert!((radius - 1.5).abs() < 1e-4, "{}", radius);
+            assert!(
+                dot(
+                    raised.normals[i],
+                    raised.positions[i] - point3::from(1., 0., 0.)
+                ) > 0.
+            );
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use crate::{
+    aabb::*, material::material::Material, objects::hittable::*, objects::sphere::*, ray::*,
+    vec3::*,
+};
+use std::sync::Arc;
+
+/// Halvings of the ray interval before a root is considered isolated
+const MAX_DEPTH: usize = 24;
+
+/// Point source of density, falling smoothly to zero at `radius`. A negative `weight` carves
+/// into the neighbouring blobs
+#[derive(Copy, Clone, Debug)]
+pub struct Blob {
+    pub center: point3,
+    pub radius: f32,
+    pub weight: f32,
+}
+
+impl Blob {
+    pub fn from(center: point3, radius: f32, weight: f32) -> Self {
+        Self {
+            center,
+            radius,
+            weight,
+        }
+    }
+    /// Kernel `(1 - r²/R²)³` of the squared distance to the center
+    fn kernel(&self, distance_squared: f32) -> f32 {
+        let x = 1. - distance_squared / (self.radius * self.radius);
+        if x <= 0. {
+            0.
+        } else {
+            self.weight * x * x * x
+        }
+    }
+    fn bbox(&self) -> Aabb {
+        let r = Vec3::from(self.radius, self.radius, self.radius);
+        Aabb::from(&(self.center - r), &(self.center + r))
+    }
+}
+
+/// Blobby surface where the summed density of the blobs equals `threshold`
+pub struct Metaballs {
+    blobs: Vec<Blob>,
+    /// Groups of blobs whose supports overlap, traced apart since no density reaches between
+    clusters: Vec<Cluster>,
+    threshold: f32,
+    bbox: Option<Aabb>,
+    mat_ptr: Arc<dyn Material>,
+}
+
+struct Cluster {
+    blobs: Vec<Blob>,
+    /// Around the positive blobs only
+    bbox: Aabb,
+}
+
+/// Representative of the group of `i` in a union-find forest, halving the path on the way
+fn group_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// Span of the ray inside the support of one blob
+struct Span {
+    blob: Blob,
+    t0: f32,
+    t1: f32,
+}
+
+impl Metaballs {
+    pub fn from(blobs: Vec<Blob>, threshold: f32, m: Arc<dyn Material>) -> Self {
+        // Union-find over the blobs whose supports overlap
+        let mut parent: Vec<usize> = (0..blobs.len()).collect();
+        for i in 0..blobs.len() {
+            for j in i + 1..blobs.len() {
+                let reach = blobs[i].radius + blobs[j].radius;
+                if (blobs[i].center - blobs[j].center).len_squared() < reach * reach {
+                    let (a, b) = (group_root(&mut parent, i), group_root(&mut parent, j));
+                    parent[a] = b;
+                }
+            }
+        }
+        let mut groups: Vec<Vec<Blob>> = vec![vec![]; blobs.len()];
+        for (i, blob) in blobs.iter().enumerate() {
+            let r = group_root(&mut parent, i);
+            groups[r].push(*blob);
+        }
+
+        // Only blobs adding density can raise the field to the threshold
+        let union = |a: Option<Aabb>, b: Aabb| Some(a.map_or(b, |a| surrounding_box(&a, &b)));
+        let clusters: Vec<Cluster> = groups
+            .into_iter()
+            .filter_map(|blobs| {
+                let bbox = blobs
+                    .iter()
+                    .filter(|b| b.weight > 0.)
+                    .map(|b| b.bbox())
+                    .fold(None, union)?;
+                Some(Cluster { blobs, bbox })
+            })
+            .collect();
+        let bbox = clusters.iter().map(|c| c.bbox).fold(None, union);
+        if bbox.is_none() {
+            eprintln!("Metaballs without any positive blob can never be hit");
+        }
+        Self {
+            blobs,
+            clusters,
+            threshold,
+            bbox,
+            mat_ptr: m,
+        }
+    }
+
+    pub fn density(&self, p: &point3) -> f32 {
+        self.blobs
+            .iter()
+            .map(|b| b.kernel((*p - b.center).len_squared()))
+            .sum()
+    }
+    pub fn gradient(&self, p: &point3) -> Vec3 {
+        self.blobs.iter().fold(Vec3::new(), |acc, b| {
+            let d = *p - b.center;
+            let r2 = b.radius * b.radius;
+            let x = 1. - d.len_squared() / r2;
+            if x <= 0. {
+                acc
+            } else {
+                acc + d * (-6. * b.weight * x * x / r2)
+            }
+        })
+    }
+
+    fn density_along(&self, spans: &[Span], r: &Ray, t: f32) -> f32 {
+        let p = r.at(t);
+        spans
+            .iter()
+            .map(|s| s.blob.kernel((p - s.blob.center).len_squared()))
+            .sum()
+    }
+
+    /// Conservative range of the density over `t0..t1`, from the closest and farthest
+    /// distance of each blob center to that piece of the ray
+    fn density_bounds(&self, spans: &[Span], r: &Ray, t0: f32, t1: f32) -> (f32, f32) {
+        let mut lo = 0.;
+        let mut hi = 0.;
+        for s in spans {
+            let (a, b) = (t0.max(s.t0), t1.min(s.t1));
+            if a > b {
+                continue;
+            }
+            let distance_squared = |t: f32| (r.at(t) - s.blob.center).len_squared();
+            let closest =
+                dot(s.blob.center - r.origin(), r.direction()) / r.direction().len_squared();
+            let near = distance_squared(closest.max(a).min(b));
+            let far = distance_squared(a).max(distance_squared(b));
+
+            // Outside of the span the blob adds nothing
+            let covered = a <= t0 && b >= t1;
+            let (strong, weak) = (
+                s.blob.kernel(near),
+                if covered { s.blob.kernel(far) } else { 0. },
+            );
+            lo += strong.min(weak);
+            hi += strong.max(weak);
+        }
+        (lo, hi)
+    }
+
+    /// First crossing of the threshold within `t0..t1`, halving the interval and discarding
+    /// the halves whose density bounds exclude the threshold
+    fn find_root(&self, spans: &[Span], r: &Ray, t0: f32, t1: f32, depth: usize) -> Option<f32> {
+        let (lo, hi) = self.density_bounds(spans, r, t0, t1);
+        if self.threshold < lo || self.threshold > hi {
+            return None;
+        }
+        if depth == MAX_DEPTH {
+            let f0 = self.density_along(spans, r, t0) - self.threshold;
+            let f1 = self.density_along(spans, r, t1) - self.threshold;
+            if (f0 < 0.) == (f1 < 0.) {
+                return None;
+            }
+            return Some(t0 + (t1 - t0) * f0 / (f0 - f1));
+        }
+        let mid = 0.5 * (t0 + t1);
+        self.find_root(spans, r, t0, mid, depth + 1)
+            .or_else(|| self.find_root(spans, r, mid, t1, depth + 1))
+    }
+
+    /// Nearest crossing of the threshold by `r` within one cluster
+    fn hit_cluster(&self, cluster: &Cluster, r: &Ray, t_min: f32, t_max: f32) -> Option<f32> {
+        let (t0, t1) = cluster.bbox.hit_interval(r, t_min, t_max)?;
+
+        let a = r.direction().len_squared();
+        let spans: Vec<Span> = cluster
+            .blobs
+            .iter()
+            .filter_map(|blob| {
+                let oc = r.origin() - blob.center;
+                let half_b = dot(oc, r.direction());
+                let c = oc.len_squared() - blob.radius * blob.radius;
+                let discriminant = half_b * half_b - a * c;
+                if discriminant <= 0. {
+                    return None;
+                }
+                let sqrtd = discriminant.sqrt();
+                let (s0, s1) = ((-half_b - sqrtd) / a, (-half_b + sqrtd) / a);
+                if s1 < t0 || s0 > t1 {
+                    return None;
+                }
+                Some(Span {
+                    blob: *blob,
+                    t0: s0.max(t0),
+                    t1: s1.min(t1),
+                })
+            })
+            .collect();
+        if !spans.iter().any(|s| s.blob.weight > 0.) {
+            return None;
+        }
+
+        let start = spans.iter().map(|s| s.t0).fold(t1, f32::min);
+        let end = spans.iter().map(|s| s.t1).fold(t0, f32::max);
+        self.find_root(&spans, r, start, end, 0)
+    }
+}
+
+impl Hittable for Metaballs {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        self.bbox?.hit_interval(r, t_min, t_max)?;
+        let mut closest = None;
+        for cluster in &self.clusters {
+            let t_max = closest.unwrap_or(t_max);
+            if let Some(t) = self.hit_cluster(cluster, r, t_min, t_max) {
+                closest = Some(t);
+            }
+        }
+        let t = closest?;
+
+        let gradient = self.gradient(&r.at(t));
+        if gradient.near_zero() {
+            return None;
+        }
+        let outward_normal = unit_vector(gradient.inv());
+        Some(
+            HitRecord::from(
+                r,
+                t,
+                outward_normal,
+                get_sphere_uv(&outward_normal),
+                &*self.mat_ptr,
+            )
+            .with_tangents(get_sphere_tangents(&outward_normal)),
+        )
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::Lambertian;
+
+    #[test]
+    fn single_blob_is_a_sphere() {
+        // (1 - r²)³ = 1/8 at r = 1/√2
+        let balls = Metaballs::from(
+            vec![Blob::from(point3::new(), 1., 1.)],
+            0.125,
+            Arc::new(Lambertian::from(color::new())),
+        );
+        let r = Ray::new(point3::from(0.3, 0., -5.), Vec3::from(0., 0., 2.), 0.);
+        let rec = balls.hit(&r, 0.001, f32::INFINITY).unwrap();
+        let expected_z = -(0.5f32 - 0.09).sqrt();
+        assert!((rec.p.z() - expected_z).abs() < 1e-4, "{:?}", rec.p);
+        assert!((rec.normal - unit_vector(rec.p)).len() < 1e-3);
+
+        let inside = Ray::new(point3::from(0., 0., 0.), Vec3::from(1., 0., 0.), 0.);
+        let rec = balls.hit(&inside, 0.001, f32::INFINITY).unwrap();
+        assert!((rec.p.x() - 0.5f32.sqrt()).abs() < 1e-4);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn separate_blobs_are_bounded_apart() {
+        let balls = Metaballs::from(
+            vec![
+                Blob::from(point3::from(-5., 0., 0.), 1., 1.),
+                Blob::from(point3::from(-4., 0., 0.), 1., 1.),
+                Blob::from(point3::from(5., 0., 0.), 1., 1.),
+                // Only overlaps the right blob, and alone could never be hit
+                Blob::from(point3::from(5.5, 0., 0.), 1., -0.5),
+                Blob::from(point3::from(0., 5., 0.), 1., -1.),
+            ],
+            0.125,
+            Arc::new(Lambertian::from(color::new())),
+        );
+        assert_eq!(balls.clusters.len(), 2);
+        assert_eq!(balls.clusters[1].blobs.len(), 2);
+        assert!((balls.clusters[0].bbox.max() - point3::from(-3., 1., 1.)).len() < 1e-6);
+        assert!((balls.clusters[1].bbox.max() - point3::from(6., 1., 1.)).len() < 1e-6);
+
+        // Between the clusters nothing is hit, while both ends of the ray hit their blob
+        let down = |x: f32| Ray::new(point3::from(x, 5., 0.), Vec3::from(0., -1., 0.), 0.);
+        assert!(balls.hit(&down(0.), 0.001, f32::INFINITY).is_none());
+        assert!(balls.hit(&down(-4.5), 0.001, f32::INFINITY).is_some());
+        assert!(balls.hit(&down(5.), 0.001, f32::INFINITY).is_some());
+        let across = Ray::new(point3::from(10., 0., 0.), Vec3::from(-1., 0., 0.), 0.);
+        let rec = balls.hit(&across, 0.001, f32::INFINITY).unwrap();
+        assert!(rec.p.x() > 4.);
+    }
+}
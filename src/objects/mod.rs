@@ -1,4 +1,13 @@
+pub mod bezier_patch;
+pub mod curve;
+pub mod heightfield;
 pub mod hittable;
 pub mod hittable_list;
+pub mod mesh;
+pub mod metaballs;
 pub mod moving_sphere;
+pub mod plane;
+pub mod scatter;
+pub mod sdf;
 pub mod sphere;
+pub mod subdivision;
@@ -1,12 +1,81 @@
-use crate::{aabb::*, material::material::*, objects::hittable::*, ray::*, vec3::*};
+use crate::{
+    aabb::*, material::material::*, objects::curve::*, objects::hittable::*, objects::sphere::*,
+    ray::*, vec3::*,
+};
 use std::sync::Arc;
 
+/// Path followed by the center of a sphere, parameterized over `0..1` of the shutter interval
+#[derive(Clone, Debug)]
+pub enum MotionPath {
+    Linear(point3, point3),
+    /// Vertices spread evenly in time
+    Polyline(Vec<point3>),
+    /// Cubic Bézier given by its control points
+    Bezier([point3; 4]),
+}
+
+impl MotionPath {
+    pub fn at(&self, s: f32) -> point3 {
+        match self {
+            MotionPath::Linear(a, b) => *a + (*b - *a) * s,
+            MotionPath::Polyline(points) => {
+                let segments = points.len() - 1;
+                if segments == 0 {
+                    return points[0];
+                }
+                let x = s * segments as f32;
+                let i = (x as usize).min(segments - 1);
+                points[i] + (points[i + 1] - points[i]) * (x - i as f32)
+            }
+            MotionPath::Bezier(cp) => eval_bezier(cp, s),
+        }
+    }
+
+    /// Box around every center the path reaches over `s0..s1`
+    fn bounds(&self, s0: f32, s1: f32) -> Aabb {
+        let around = |points: &[point3]| {
+            points
+                .iter()
+                .skip(1)
+                .fold(Aabb::from(&points[0], &points[0]), |b, p| {
+                    surrounding_box(&b, &Aabb::from(p, p))
+                })
+        };
+        match self {
+            MotionPath::Linear(..) => around(&[self.at(s0), self.at(s1)]),
+            MotionPath::Polyline(points) => {
+                // Within a segment the center moves in a straight line, so the corners suffice
+                let segments = (points.len() - 1) as f32;
+                let mut corners = vec![self.at(s0), self.at(s1)];
+                corners.extend(
+                    points
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| {
+                            let s = *i as f32 / segments;
+                            s > s0 && s < s1
+                        })
+                        .map(|(_, p)| *p),
+                );
+                around(&corners)
+            }
+            MotionPath::Bezier(cp) => {
+                // The piece of the curve over s0..s1 lies in the hull of its own control points
+                let (_, tail) = split_bezier_at(cp, s0);
+                let (piece, _) =
+                    split_bezier_at(&tail, if s0 < 1. { (s1 - s0) / (1. - s0) } else { 0. });
+                around(&piece)
+            }
+        }
+    }
+}
+
 pub struct MovingSphere {
-    pub center0: point3,
-    pub center1: point3,
+    pub path: MotionPath,
     pub time0: f32,
     pub time1: f32,
-    pub radius: f32,
+    pub radius0: f32,
+    pub radius1: f32,
     pub mat_ptr: Arc<dyn Material>,
 }
 
@@ -19,44 +88,77 @@ impl MovingSphere {
         r: f32,
         m: Arc<dyn Material>,
     ) -> Self {
+        Self::from_path(MotionPath::Linear(cen0, cen1), time0, time1, r, m)
+    }
+    pub fn from_path(
+        path: MotionPath,
+        time0: f32,
+        time1: f32,
+        r: f32,
+        m: Arc<dyn Material>,
+    ) -> Self {
+        let path = match path {
+            MotionPath::Polyline(points) if points.is_empty() => {
+                eprintln!("MovingSphere path without any point");
+                MotionPath::Polyline(vec![point3::new()])
+            }
+            path => path,
+        };
         Self {
-            center0: cen0,
-            center1: cen1,
+            path,
             time0,
             time1,
-            radius: r,
+            radius0: r,
+            radius1: r,
             mat_ptr: m,
         }
     }
+    /// Grows or shrinks the radius linearly to `r` at the end of the shutter interval
+    pub fn with_end_radius(mut self, r: f32) -> Self {
+        self.radius1 = r;
+        self
+    }
+
+    fn shutter_fraction(&self, time: f32) -> f32 {
+        if self.time1 == self.time0 {
+            return 0.;
+        }
+        ((time - self.time0) / (self.time1 - self.time0)).clamp(0., 1.)
+    }
     pub fn center(&self, time: f32) -> point3 {
-        self.center0
-            + (self.center1 - self.center0) * ((time - self.time0) / (self.time1 - self.time0))
+        self.path.at(self.shutter_fraction(time))
+    }
+    pub fn radius(&self, time: f32) -> f32 {
+        let s = self.shutter_fraction(time);
+        self.radius0 + (self.radius1 - self.radius0) * s
     }
 }
 impl Hittable for MovingSphere {
-    fn bounding_box<'a>(&'a self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-        let box0 = Aabb::from(
-            &Vec3::from(self.radius, self.radius, self.radius + -time0),
-            &Vec3::from(self.radius, self.radius, self.radius + time0),
-        );
-        let box1 = Aabb::from(
-            &Vec3::from(self.radius, self.radius, self.radius + -time1),
-            &Vec3::from(self.radius, self.radius, self.radius + time1),
-        );
-        *output_box = surrounding_box(&box0, &box1);
-        true
+    fn bounding_box(&self, time0: f32, time1: f32) -> Option<Aabb> {
+        let (s0, s1) = (self.shutter_fraction(time0), self.shutter_fraction(time1));
+        let r = self.radius(time0).max(self.radius(time1));
+        let radius = Vec3::from(r, r, r);
+        let centers = self.path.bounds(s0.min(s1), s0.max(s1));
+        Some(Aabb::from(
+            &(centers.min() - radius),
+            &(centers.max() + radius),
+        ))
     }
-    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
-        let oc = r.origin() - self.center(r.time());
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let center = self.center(r.time());
+        let radius = self.radius(r.time());
+        if radius <= 0. {
+            return None;
+        }
+        let oc = r.origin() - center;
         let a = r.direction().len_squared();
         let half_b = dot(oc, r.direction());
-        let c = oc.len_squared() - self.radius * self.radius;
-        let mut front_face = false;
+        let c = oc.len_squared() - radius * radius;
 
         let discriminant = half_b * half_b - a * c;
 
         if discriminant < 0. {
-            return false;
+            return None;
         }
 
         let sqrtd = discriminant.sqrt();
@@ -66,16 +168,62 @@ impl Hittable for MovingSphere {
         if root < t_min || t_max < root {
             root = (-half_b + sqrtd) / a;
             if root < t_min || t_max < root {
-                return false;
+                return None;
             }
         }
 
-        rec.t = root;
-        rec.p = r.at(rec.t);
-        let outward_normal = (rec.p - self.center(r.time())) / self.radius;
-        rec.set_face_normal(*r, outward_normal);
-        rec.mat_ptr = &*self.mat_ptr;
+        let outward_normal = (r.at(root) - center) / radius;
+        let (dpdu, dpdv) = get_sphere_tangents(&outward_normal);
+        Some(
+            HitRecord::from(
+                r,
+                root,
+                outward_normal,
+                get_sphere_uv(&outward_normal),
+                &*self.mat_ptr,
+            )
+            .with_tangents((dpdu * radius, dpdv * radius)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        true
+    #[test]
+    fn swept_box_holds_every_sphere_of_the_interval() {
+        let m = Arc::new(Lambertian::from(color::new()));
+        let paths = vec![
+            MotionPath::Polyline(vec![
+                point3::new(),
+                point3::from(2., 3., 0.),
+                point3::from(4., -1., 1.),
+            ]),
+            MotionPath::Bezier([
+                point3::new(),
+                point3::from(0., 4., 0.),
+                point3::from(3., 4., -2.),
+                point3::from(3., 0., 0.),
+            ]),
+        ];
+        for path in paths {
+            let sphere = MovingSphere::from_path(path, 0., 2., 0.5, m.clone()).with_end_radius(1.);
+            let (t0, t1) = (0.3, 1.7);
+            let bbox = sphere.bounding_box(t0, t1).unwrap();
+            for i in 0..=100 {
+                let time = t0 + (t1 - t0) * i as f32 / 100.;
+                let c = sphere.center(time);
+                let r = sphere.radius(time);
+                for a in 0..3 {
+                    assert!(c.e[a] - r >= bbox.min().e[a] - 1e-4);
+                    assert!(c.e[a] + r <= bbox.max().e[a] + 1e-4);
+                }
+
+                let ray = Ray::new(c + Vec3::from(0., 0., 10.), Vec3::from(0., 0., -1.), time);
+                let rec = sphere.hit(&ray, 0.001, f32::INFINITY).unwrap();
+                assert!((rec.t - (10. - r)).abs() < 1e-3);
+            }
+        }
     }
 }
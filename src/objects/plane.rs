@@ -0,0 +1,88 @@
+use crate::{aabb::*, material::material::Material, objects::hittable::*, onb::*, ray::*, vec3::*};
+use std::sync::Arc;
+
+/// Infinite plane through `point`. It has no bounding box, so BVHs keep it beside their tree.
+/// Texture coordinates repeat every `tile_size` along two axes of the plane
+pub struct Plane {
+    point: point3,
+    normal: Vec3,
+    frame: Onb,
+    tile_size: f32,
+    mat_ptr: Arc<dyn Material>,
+}
+
+impl Plane {
+    pub fn from(point: point3, normal: Vec3, m: Arc<dyn Material>) -> Self {
+        Self {
+            point,
+            normal: unit_vector(normal),
+            frame: Onb::build_from_w(normal),
+            tile_size: 1.,
+            mat_ptr: m,
+        }
+    }
+    pub fn with_tile_size(mut self, tile_size: f32) -> Self {
+        self.tile_size = tile_size;
+        self
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let denom = dot(self.normal, r.direction());
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+        let t = dot(self.point - r.origin(), self.normal) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let d = r.at(t) - self.point;
+        let u = (dot(d, self.frame.u()) / self.tile_size).rem_euclid(1.);
+        let v = (dot(d, self.frame.v()) / self.tile_size).rem_euclid(1.);
+        Some(
+            HitRecord::from(r, t, self.normal, (u, v), &*self.mat_ptr).with_tangents((
+                self.frame.u() * self.tile_size,
+                self.frame.v() * self.tile_size,
+            )),
+        )
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bvh::*, material::material::Lambertian, objects::hittable_list::*, objects::sphere::*,
+    };
+
+    #[test]
+    fn plane_is_kept_beside_the_bvh() {
+        let m = Arc::new(Lambertian::from(color::new()));
+        let mut list = HittableList::new();
+        list.add(Arc::new(Sphere::new(
+            point3::from(0., 1., 0.),
+            0.5,
+            m.clone(),
+        )));
+        list.add(Arc::new(
+            Plane::from(point3::new(), Vec3::from(0., 1., 0.), m).with_tile_size(2.),
+        ));
+        let bvh = BvhNode::from(&mut list, 0., 1.);
+        assert!(bvh.bounding_box(0., 1.).is_none());
+
+        let r = Ray::new(point3::from(3., 5., 1.), Vec3::from(0., -1., 0.), 0.);
+        let rec = bvh.hit(&r, 0.001, f32::INFINITY).unwrap();
+        assert!((rec.t - 5.).abs() < 1e-5);
+        assert!(rec.normal.y() > 0.);
+        assert!((rec.u - 0.5).abs() < 1e-5 || (rec.v - 0.5).abs() < 1e-5);
+
+        let r = Ray::new(point3::from(0., 5., 0.), Vec3::from(0., -1., 0.), 0.);
+        assert!((bvh.hit(&r, 0.001, f32::INFINITY).unwrap().t - 3.5).abs() < 1e-5);
+    }
+}
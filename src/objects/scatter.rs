@@ -0,0 +1,241 @@
+use crate::{
+    aabb::*, objects::hittable::*, objects::sphere::get_sphere_uv, ray::*, texture::*, tlas::*,
+    vec3::*,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::Arc;
+
+/// Where scattered instances are placed
+pub enum ScatterDomain {
+    /// Rectangle of the plane `y = k`
+    XzRect {
+        x0: f32,
+        x1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+    },
+    SphereSurface {
+        center: point3,
+        radius: f32,
+    },
+    /// Surface of an object, found by dropping rays straight down over its footprint
+    DropOnto(Arc<dyn Hittable>),
+    /// Anywhere inside a box, with the up direction picked at random
+    Volume(Aabb),
+}
+
+/// Places many copies of one prototype over a domain, each an `Instance` sharing its geometry
+pub struct Scatter {
+    prototype: Arc<dyn Hittable>,
+    domain: ScatterDomain,
+    count: usize,
+    seed: u64,
+    density: Option<Arc<dyn Texture>>,
+    align_to_normal: bool,
+    rotation_jitter: f32,
+    tilt_jitter: f32,
+    scale: (f32, f32),
+}
+
+impl Scatter {
+    pub fn from(prototype: Arc<dyn Hittable>, domain: ScatterDomain, count: usize) -> Self {
+        Self {
+            prototype,
+            domain,
+            count,
+            seed: 0,
+            density: None,
+            align_to_normal: true,
+            rotation_jitter: 360.,
+            tilt_jitter: 0.,
+            scale: (1., 1.),
+        }
+    }
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    /// Keeps a candidate with a probability given by the texture's average channel at its
+    /// `(u, v, p)`, so black areas stay empty
+    pub fn with_density(mut self, density: Arc<dyn Texture>) -> Self {
+        self.density = Some(density);
+        self
+    }
+    /// Stands instances along the surface normal instead of world +y
+    pub fn aligned_to_normal(mut self, align: bool) -> Self {
+        self.align_to_normal = align;
+        self
+    }
+    /// Maximum spin around the up direction, in degrees
+    pub fn with_rotation_jitter(mut self, degrees: f32) -> Self {
+        self.rotation_jitter = degrees;
+        self
+    }
+    /// Maximum lean away from the up direction, in degrees
+    pub fn with_tilt_jitter(mut self, degrees: f32) -> Self {
+        self.tilt_jitter = degrees;
+        self
+    }
+    pub fn with_scale(mut self, min: f32, max: f32) -> Self {
+        self.scale = (min, max);
+        self
+    }
+
+    /// Draws a point of the domain as `(p, normal, (u, v))`, `None` when the draw missed
+    fn sample_domain(&self, rng: &mut StdRng) -> Option<(point3, Vec3, (f32, f32))> {
+        match &self.domain {
+            ScatterDomain::XzRect { x0, x1, z0, z1, k } => {
+                let (u, v): (f32, f32) = (rng.gen(), rng.gen());
+                Some((
+                    point3::from(x0 + u * (x1 - x0), *k, z0 + v * (z1 - z0)),
+                    Vec3::from(0., 1., 0.),
+                    (u, v),
+                ))
+            }
+            ScatterDomain::SphereSurface { center, radius } => {
+                let n = random_unit_vector(rng);
+                Some((*center + n * *radius, n, get_sphere_uv(&n)))
+            }
+            ScatterDomain::DropOnto(target) => {
+                let bbox = target.bounding_box(0., 1.)?;
+                let (min, max) = (bbox.min(), bbox.max());
+                let origin = point3::from(
+                    rng.gen_range(min.x(), max.x()),
+                    max.y() + 1.,
+                    rng.gen_range(min.z(), max.z()),
+                );
+                let rec = target.hit(
+                    &Ray::new(origin, Vec3::from(0., -1., 0.), 0.),
+                    0.,
+                    f32::INFINITY,
+                )?;
+                let normal = if rec.front_face {
+                    rec.normal
+                } else {
+                    rec.normal.inv()
+                };
+                Some((rec.p, normal, (rec.u, rec.v)))
+            }
+            ScatterDomain::Volume(bbox) => {
+                let (min, max) = (bbox.min(), bbox.max());
+                let p = point3::from(
+                    rng.gen_range(min.x(), max.x()),
+                    rng.gen_range(min.y(), max.y()),
+                    rng.gen_range(min.z(), max.z()),
+                );
+                let (u, v) = (
+                    (p.x() - min.x()) / (max.x() - min.x()),
+                    (p.z() - min.z()) / (max.z() - min.z()),
+                );
+                Some((p, random_unit_vector(rng), (u, v)))
+            }
+        }
+    }
+
+    pub fn instances(&self) -> Vec<Instance> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut instances = Vec::with_capacity(self.count);
+
+        // Bounded so that an almost empty density map cannot stall the scene setup
+        let max_attempts = 100 * self.count.max(1);
+        let mut attempts = 0;
+        while instances.len() < self.count && attempts < max_attempts {
+            attempts += 1;
+            let (p, normal, (u, v)) = match self.sample_domain(&mut rng) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            if let Some(density) = &self.density {
+                let d = density.value(u, v, &p);
+                if rng.gen::<f32>() >= (d.x() + d.y() + d.z()) / 3. {
+                    continue;
+                }
+            }
+
+            let up = if self.align_to_normal {
+                normal
+            } else {
+                Vec3::from(0., 1., 0.)
+            };
+            let up = up + random_unit_vector(&mut rng) * self.tilt_jitter.to_radians().sin();
+            let angle = rng.gen::<f32>() * self.rotation_jitter;
+            let scale = self.scale.0 + rng.gen::<f32>() * (self.scale.1 - self.scale.0);
+
+            instances.push(Instance::from(self.prototype.clone(), up, angle, scale, p));
+        }
+
+        if instances.len() < self.count {
+            eprintln!(
+                "Scatter placed only {} of {} instances",
+                instances.len(),
+                self.count
+            );
+        }
+        instances
+    }
+
+    /// Builds a top-level structure over the instances, all sharing the prototype
+    pub fn build(&self) -> Tlas {
+        Tlas::from(self.instances())
+    }
+}
+
+fn random_unit_vector(rng: &mut StdRng) -> Vec3 {
+    loop {
+        let p = Vec3::from(
+            rng.gen_range(-1., 1.),
+            rng.gen_range(-1., 1.),
+            rng.gen_range(-1., 1.),
+        );
+        let len_squared = p.len_squared();
+        if len_squared > 1e-6 && len_squared < 1. {
+            return p / len_squared.sqrt();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::material::Lambertian, objects::sphere::Sphere};
+
+    fn centers(scatter: &Scatter) -> Vec<point3> {
+        scatter
+            .instances()
+            .iter()
+            .map(|instance| {
+                let bbox = instance.bounding_box(0., 1.).unwrap();
+                (bbox.min() + bbox.max()) * 0.5
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_same_placement_on_the_target() {
+        let matte = Arc::new(Lambertian::from(color::new()));
+        let pebble: Arc<dyn Hittable> = Arc::new(Sphere::new(point3::new(), 0.01, matte.clone()));
+        let center = point3::from(1., 2., 3.);
+        let target = Arc::new(Sphere::new(center, 2., matte));
+
+        let scatter = |seed| {
+            Scatter::from(pebble.clone(), ScatterDomain::DropOnto(target.clone()), 50)
+                .with_seed(seed)
+        };
+        let a = centers(&scatter(7));
+        let b = centers(&scatter(7));
+        let c = centers(&scatter(8));
+        assert_eq!(a.len(), 50);
+        assert!(a.iter().zip(&b).all(|(p, q)| (*p - *q).len() == 0.));
+        assert!(a.iter().zip(&c).any(|(p, q)| (*p - *q).len() > 0.));
+        for p in &a {
+            assert!(((*p - center).len() - 2.).abs() < 1e-3, "{:?}", p);
+        }
+
+        let bounds = Aabb::from(&point3::from(-1., 0., -1.), &point3::from(1., 2., 1.));
+        let volume = Scatter::from(pebble, ScatterDomain::Volume(bounds), 50).with_seed(3);
+        for p in &centers(&volume) {
+            assert!(bounds.contains(p, 1e-5), "{:?}", p);
+        }
+    }
+}
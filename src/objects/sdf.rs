@@ -0,0 +1,326 @@
+use crate::{
+    aabb::*, material::material::Material, objects::hittable::*, objects::sphere::*, ray::*,
+    vec3::*,
+};
+use std::sync::Arc;
+
+/// A signed distance function: negative inside the surface, positive outside
+pub trait DistanceField: Send + Sync {
+    fn distance(&self, p: &point3) -> f32;
+}
+
+impl<F> DistanceField for F
+where
+    F: Fn(&point3) -> f32 + Send + Sync,
+{
+    fn distance(&self, p: &point3) -> f32 {
+        self(p)
+    }
+}
+
+/// Ray-marched surface of a distance field, restricted to a bounding box
+pub struct Sdf {
+    pub field: Arc<dyn DistanceField>,
+    pub bbox: Aabb,
+    pub mat_ptr: Arc<dyn Material>,
+    pub epsilon: f32,
+    pub max_steps: usize,
+}
+
+impl Sdf {
+    pub fn from(field: Arc<dyn DistanceField>, bbox: Aabb, m: Arc<dyn Material>) -> Self {
+        Self {
+            field,
+            bbox,
+            mat_ptr: m,
+            epsilon: 1e-4,
+            max_steps: 256,
+        }
+    }
+    pub fn with_budget(mut self, epsilon: f32, max_steps: usize) -> Self {
+        self.epsilon = epsilon;
+        self.max_steps = max_steps;
+        self
+    }
+    /// Normal from the central-difference gradient of the field
+    pub fn normal(&self, p: &point3) -> Vec3 {
+        let h = self.epsilon;
+        let mut n = Vec3::new();
+        for i in 0..3 {
+            let mut offset = Vec3::new();
+            offset[i] = h;
+            n[i] = self.field.distance(&(*p + offset)) - self.field.distance(&(*p - offset));
+        }
+        unit_vector(n)
+    }
+}
+
+impl Hittable for Sdf {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+        let (t0, t1) = self.bbox.hit_interval(r, t_min, t_max)?;
+        let ray_length = r.direction().len();
+
+        // Rays entering the box start outside the surface. Rays starting inside it march on
+        // the negated field, and rays leaving the surface pick their side from the gradient
+        // and must escape it before hitting again
+        let start = r.at(t0);
+        let d0 = self.field.distance(&start);
+        let entering = t0 > t_min;
+        let sign = if entering {
+            1.
+        } else if d0.abs() < self.epsilon {
+            if dot(self.normal(&start), r.direction()) > 0. {
+                1.
+            } else {
+                -1.
+            }
+        } else if d0 < 0. {
+            -1.
+        } else {
+            1.
+        };
+
+        let mut t = t0;
+        let mut escaped = entering;
+        for _ in 0..self.max_steps {
+            if t > t1 {
+                return None;
+            }
+            let p = r.at(t);
+            let d = sign * self.field.distance(&p);
+
+            if d < self.epsilon {
+                if !escaped {
+                    t += self.epsilon / ray_length;
+                    continue;
+                }
+                let outward_normal = self.normal(&p);
+                return Some(
+                    HitRecord::from(
+                        r,
+                        t,
+                        outward_normal,
+                        get_sphere_uv(&outward_normal),
+                        &*self.mat_ptr,
+                    )
+                    .with_tangents(get_sphere_tangents(&outward_normal)),
+                );
+            }
+            escaped = true;
+            t += d / ray_length;
+        }
+        None
+    }
+
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        Some(self.bbox)
+    }
+}
+
+pub struct SdfSphere {
+    pub center: point3,
+    pub radius: f32,
+}
+
+impl DistanceField for SdfSphere {
+    fn distance(&self, p: &point3) -> f32 {
+        (*p - self.center).len() - self.radius
+    }
+}
+
+pub struct SdfRoundBox {
+    pub center: point3,
+    pub half_extents: Vec3,
+    pub rounding: f32,
+}
+
+impl DistanceField for SdfRoundBox {
+    fn distance(&self, p: &point3) -> f32 {
+        let local = *p - self.center;
+        let mut q = Vec3::new();
+        for i in 0..3 {
+            q[i] = local[i].abs() - self.half_extents[i] + self.rounding;
+        }
+        let outside = Vec3::from(q.x().max(0.), q.y().max(0.), q.z().max(0.));
+        outside.len() + q.x().max(q.y()).max(q.z()).min(0.) - self.rounding
+    }
+}
+
+pub struct SdfTorus {
+    pub center: point3,
+    pub major_radius: f32,
+    pub minor_radius: f32,
+}
+
+impl DistanceField for SdfTorus {
+    fn distance(&self, p: &point3) -> f32 {
+        let local = *p - self.center;
+        let ring = (local.x() * local.x() + local.z() * local.z()).sqrt() - self.major_radius;
+        (ring * ring + local.y() * local.y()).sqrt() - self.minor_radius
+    }
+}
+
+/// Distance estimator for the power-n Mandelbulb, centered on `center` and `scale` times the
+/// size of the unit bulb
+pub struct Mandelbulb {
+    pub center: point3,
+    pub scale: f32,
+    pub power: f32,
+    pub iterations: usize,
+}
+
+impl Mandelbulb {
+    pub fn from(center: point3, scale: f32) -> Self {
+        Self {
+            center,
+            scale,
+            power: 8.,
+            iterations: 12,
+        }
+    }
+}
+
+impl DistanceField for Mandelbulb {
+    fn distance(&self, p: &point3) -> f32 {
+        let c = (*p - self.center) / self.scale;
+        let mut z = c;
+        let mut dr = 1.;
+        let mut r = 0.;
+
+        for _ in 0..self.iterations {
+            r = z.len();
+            if r > 2. {
+                break;
+            }
+            // The angles are undefined at the origin, where z^n vanishes
+            if r == 0. {
+                z = c;
+                dr = 1.;
+                continue;
+            }
+            let theta = (z.z() / r).acos() * self.power;
+            let phi = z.y().atan2(z.x()) * self.power;
+            let zr = r.powf(self.power);
+            dr = r.powf(self.power - 1.) * self.power * dr + 1.;
+
+            z = Vec3::from(
+                theta.sin() * phi.cos(),
+                phi.sin() * theta.sin(),
+                theta.cos(),
+            ) * zr
+                + c;
+        }
+        // An orbit stuck at the origin is inside the set
+        if r == 0. {
+            return 0.;
+        }
+        0.5 * r.ln() * r / dr * self.scale
+    }
+}
+
+pub struct Union {
+    pub a: Arc<dyn DistanceField>,
+    pub b: Arc<dyn DistanceField>,
+}
+
+impl DistanceField for Union {
+    fn distance(&self, p: &point3) -> f32 {
+        self.a.distance(p).min(self.b.distance(p))
+    }
+}
+
+/// Union of two fields, blended over a distance of `k`
+pub struct SmoothUnion {
+    pub a: Arc<dyn DistanceField>,
+    pub b: Arc<dyn DistanceField>,
+    pub k: f32,
+}
+
+impl DistanceField for SmoothUnion {
+    fn distance(&self, p: &point3) -> f32 {
+        let d1 = self.a.distance(p);
+        let d2 = self.b.distance(p);
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0., 1.);
+        d2 + (d1 - d2) * h - self.k * h * (1. - h)
+    }
+}
+
+/// `a` with `b` carved out of it, blended over a distance of `k`
+pub struct SmoothSubtraction {
+    pub a: Arc<dyn DistanceField>,
+    pub b: Arc<dyn DistanceField>,
+    pub k: f32,
+}
+
+impl DistanceField for SmoothSubtraction {
+    fn distance(&self, p: &point3) -> f32 {
+        let d1 = self.a.distance(p);
+        let d2 = self.b.distance(p);
+        let h = (0.5 - 0.5 * (d1 + d2) / self.k).clamp(0., 1.);
+        d1 + (-d2 - d1) * h + self.k * h * (1. - h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::Lambertian;
+
+    fn unit_sphere() -> Sdf {
+        Sdf::from(
+            Arc::new(SdfSphere {
+                center: point3::new(),
+                radius: 1.,
+            }),
+            Aabb::from(&point3::from(-1., -1., -1.), &point3::from(1., 1., 1.)),
+            Arc::new(Lambertian::from(color::new())),
+        )
+    }
+
+    #[test]
+    fn hits_sphere_from_outside() {
+        let sdf = unit_sphere();
+        let r = Ray::new(point3::from(0., 0., -5.), Vec3::from(0., 0., 2.), 0.);
+        let rec = sdf.hit(&r, 0.001, f32::INFINITY).unwrap();
+
+        assert!((rec.t - 2.).abs() < 1e-3);
+        assert!(rec.front_face);
+        assert!((rec.normal.z() + 1.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn hits_sphere_from_inside() {
+        let sdf = unit_sphere();
+        let r = Ray::new(point3::new(), Vec3::from(1., 0., 0.), 0.);
+        let rec = sdf.hit(&r, 0.001, f32::INFINITY).unwrap();
+
+        assert!((rec.t - 1.).abs() < 1e-3);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn smooth_union_is_below_both_fields() {
+        let a: Arc<dyn DistanceField> = Arc::new(SdfSphere {
+            center: point3::from(-0.5, 0., 0.),
+            radius: 1.,
+        });
+        let b: Arc<dyn DistanceField> = Arc::new(SdfSphere {
+            center: point3::from(0.5, 0., 0.),
+            radius: 1.,
+        });
+        let blend = SmoothUnion {
+            a: a.clone(),
+            b: b.clone(),
+            k: 0.5,
+        };
+        let p = point3::from(0., 1., 0.);
+        assert!(blend.distance(&p) <= a.distance(&p).min(b.distance(&p)));
+    }
+
+    #[test]
+    fn mandelbulb_center_is_inside() {
+        let bulb = Mandelbulb::from(point3::from(1., 2., 3.), 2.);
+        assert_eq!(bulb.distance(&point3::from(1., 2., 3.)), 0.);
+        assert!(bulb.distance(&point3::from(1., 2., 10.)) > 0.);
+    }
+}
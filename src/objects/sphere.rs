@@ -16,26 +16,42 @@ impl Sphere {
         }
     }
 }
-pub fn get_sphere_uv(p: &point3, u: &mut f32, v: &mut f32) {
+/// Maps a point of the unit sphere to `(u, v)` texture coordinates
+pub fn get_sphere_uv(p: &point3) -> (f32, f32) {
     let theta = p.inv().y().acos();
     let phi = p.inv().z().atan2(p.x()) + PI;
 
-    *u = phi / (2. * PI);
-    *v = theta / PI;
+    (phi / (2. * PI), theta / PI)
+}
+/// Derivatives of a point of the unit sphere along the `(u, v)` of `get_sphere_uv`
+pub fn get_sphere_tangents(p: &point3) -> (Vec3, Vec3) {
+    let dpdu = Vec3::from(p.z(), 0., -p.x()) * (2. * PI);
+
+    // At the poles the v derivative is picked along the u = 0 meridian
+    let sin_theta = (p.x() * p.x() + p.z() * p.z()).sqrt();
+    let dpdv = if sin_theta > 1e-6 {
+        Vec3::from(
+            -p.x() * p.y() / sin_theta,
+            sin_theta,
+            -p.y() * p.z() / sin_theta,
+        ) * PI
+    } else {
+        Vec3::from(p.y(), 0., 0.) * PI
+    };
+    (dpdu, dpdv)
 }
 
 impl Hittable for Sphere {
-    fn hit<'a>(&'a self, r: &Ray, t_min: f32, t_max: f32, rec: &mut HitRecord<'a>) -> bool {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
         let oc = r.origin() - self.center;
         let a = r.direction().len_squared();
         let half_b = dot(oc, r.direction());
         let c = oc.len_squared() - self.radius * self.radius;
-        let mut front_face = false;
 
         let discriminant = half_b * half_b - a * c;
 
         if discriminant < 0. {
-            return false;
+            return None;
         }
 
         let sqrtd = discriminant.sqrt();
@@ -45,25 +61,28 @@ impl Hittable for Sphere {
         if root < t_min || t_max < root {
             root = (-half_b + sqrtd) / a;
             if root < t_min || t_max < root {
-                return false;
+                return None;
             }
         }
 
-        rec.t = root;
-        rec.p = r.at(rec.t);
-        let outward_normal = (rec.p - self.center) / self.radius;
-        rec.set_face_normal(*r, outward_normal);
-        get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
-        rec.mat_ptr = &*self.mat_ptr;
-
-        true
+        let outward_normal = (r.at(root) - self.center) / self.radius;
+        let (dpdu, dpdv) = get_sphere_tangents(&outward_normal);
+        Some(
+            HitRecord::from(
+                r,
+                root,
+                outward_normal,
+                get_sphere_uv(&outward_normal),
+                &*self.mat_ptr,
+            )
+            .with_tangents((dpdu * self.radius, dpdv * self.radius)),
+        )
     }
 
-    fn bounding_box<'a>(&'a self, time0: f32, time1: f32, output_box: &mut Aabb) -> bool {
-        *output_box = Aabb::from(
+    fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+        Some(Aabb::from(
             &(self.center - Vec3::from(self.radius, self.radius, self.radius)),
             &(self.center + Vec3::from(self.radius, self.radius, self.radius)),
-        );
-        true
+        ))
     }
 }
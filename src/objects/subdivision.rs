@@ -0,0 +1,529 @@
+use crate::{objects::mesh::*, vec3::*};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fs;
+
+/// Polygon mesh as authored, refined by Loop or Catmull–Clark subdivision into a triangle
+/// `Mesh`. Faces must share a consistent winding
+#[derive(Clone, Default)]
+pub struct PolyMesh {
+    pub positions: Vec<point3>,
+    pub faces: Vec<Vec<usize>>,
+    /// Sharpness of creased edges, keyed by their vertices in increasing order. Boundary
+    /// edges are always sharp
+    pub creases: HashMap<(usize, usize), f32>,
+}
+
+/// Adjacency of a `PolyMesh`, rebuilt at every level
+struct Topology {
+    edge_faces: HashMap<(usize, usize), Vec<usize>>,
+    neighbors: Vec<Vec<usize>>,
+    vertex_faces: Vec<Vec<usize>>,
+}
+
+fn edge(a: usize, b: usize) -> (usize, usize) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+impl PolyMesh {
+    /// Faces of fewer than three vertices are dropped
+    pub fn from(positions: Vec<point3>, faces: Vec<Vec<usize>>) -> Self {
+        let faces = faces
+            .into_iter()
+            .filter(|face| {
+                if face.len() < 3 {
+                    eprintln!("Skipping face {:?} with fewer than three vertices", face);
+                }
+                face.len() >= 3
+            })
+            .collect();
+        Self {
+            positions,
+            faces,
+            creases: HashMap::new(),
+        }
+    }
+    /// Loads the vertices and faces of a Wavefront OBJ file, ignoring everything else
+    pub fn from_obj_file(filename: &str) -> Self {
+        let text = match fs::read_to_string(filename) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("ERROR: Could not read mesh file {}: {}", filename, e);
+                return Self::default();
+            }
+        };
+
+        let mut positions = vec![];
+        let mut faces = vec![];
+        for line in text.lines() {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("v") => {
+                    let c: Vec<f32> = fields.filter_map(|f| f.parse().ok()).collect();
+                    if c.len() >= 3 {
+                        positions.push(point3::from(c[0], c[1], c[2]));
+                    }
+                }
+                Some("f") => {
+                    // Corners look like `v`, `v/vt`, `v//vn` or `v/vt/vn`, negative indices
+                    // counting back from the last vertex
+                    let face: Option<Vec<usize>> = fields
+                        .map(|corner| {
+                            let i: i64 = corner.split('/').next()?.parse().ok()?;
+                            let i = if i < 0 {
+                                positions.len() as i64 + i
+                            } else {
+                                i - 1
+                            };
+                            if i >= 0 && (i as usize) < positions.len() {
+                                Some(i as usize)
+                            } else {
+                                None
+                            }
+                        })
+                        .collect();
+                    match face {
+                        Some(face) if face.len() >= 3 => faces.push(face),
+                        _ => eprintln!("Skipping malformed face `{}` in {}", line, filename),
+                    }
+                }
+                _ => {}
+            }
+        }
+        Self::from(positions, faces)
+    }
+    /// Marks the edge between two vertices as creased. Sharpness decreases by one per level,
+    /// so an edge of sharpness 2 stays sharp over two levels and then smooths out
+    pub fn with_crease(mut self, a: usize, b: usize, sharpness: f32) -> Self {
+        self.creases.insert(edge(a, b), sharpness);
+        self
+    }
+
+    /// Splits every face into a triangle fan, without smoothing
+    pub fn triangulate(&self) -> Mesh {
+        let mut indices = vec![];
+        for face in &self.faces {
+            for k in 1..face.len().saturating_sub(1) {
+                indices.push([face[0], face[k], face[k + 1]]);
+            }
+        }
+        Mesh::from(self.positions.clone(), indices)
+    }
+
+    /// Loop subdivision, for triangle meshes. Other polygons are fanned into triangles first
+    pub fn loop_subdivide(&self, levels: usize) -> Mesh {
+        let mut mesh = Self {
+            faces: self
+                .triangulate()
+                .indices
+                .iter()
+                .map(|t| t.to_vec())
+                .collect(),
+            ..self.clone()
+        };
+        for _ in 0..levels {
+            mesh = mesh.loop_step();
+        }
+        mesh.limit_mesh(3)
+    }
+
+    /// Catmull–Clark subdivision, turning any polygon mesh into quads after one level
+    pub fn catmull_clark(&self, levels: usize) -> Mesh {
+        let mut mesh = self.clone();
+        for _ in 0..levels {
+            mesh = mesh.catmull_clark_step();
+        }
+        mesh.limit_mesh(4)
+    }
+
+    fn topology(&self) -> Topology {
+        let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        let mut neighbors = vec![vec![]; self.positions.len()];
+        let mut vertex_faces = vec![vec![]; self.positions.len()];
+        for (f, face) in self.faces.iter().enumerate() {
+            for k in 0..face.len() {
+                let (a, b) = (face[k], face[(k + 1) % face.len()]);
+                let faces = edge_faces.entry(edge(a, b)).or_default();
+                if faces.is_empty() {
+                    neighbors[a].push(b);
+                    neighbors[b].push(a);
+                }
+                faces.push(f);
+                vertex_faces[a].push(f);
+            }
+        }
+        Topology {
+            edge_faces,
+            neighbors,
+            vertex_faces,
+        }
+    }
+
+    fn sharpness(&self, topo: &Topology, e: (usize, usize)) -> f32 {
+        if topo.edge_faces.get(&e).map_or(0, |f| f.len()) != 2 {
+            return f32::INFINITY;
+        }
+        self.creases.get(&e).copied().unwrap_or(0.)
+    }
+
+    /// Applies the crease rules to the `smooth` position of vertex `v`: vertices on one
+    /// sharp edge stay smooth, on two they follow the crease curve and on more they are fixed
+    fn vertex_point(&self, topo: &Topology, v: usize, smooth: point3) -> point3 {
+        let sharp: Vec<(usize, f32)> = topo.neighbors[v]
+            .iter()
+            .map(|&n| (n, self.sharpness(topo, edge(v, n))))
+            .filter(|&(_, s)| s > 0.)
+            .collect();
+        let p = &self.positions;
+        let target = match sharp.len() {
+            0 | 1 => return smooth,
+            2 => (p[sharp[0].0] + p[v] * 6. + p[sharp[1].0]) / 8.,
+            _ => p[v],
+        };
+        let weight = sharp.iter().map(|&(_, s)| s.min(1.)).sum::<f32>() / sharp.len() as f32;
+        smooth * (1. - weight) + target * weight
+    }
+
+    /// Blends the `smooth` position of the point splitting edge `e` towards its midpoint
+    /// according to the edge sharpness
+    fn edge_point(&self, topo: &Topology, e: (usize, usize), smooth: point3) -> point3 {
+        let weight = self.sharpness(topo, e).min(1.);
+        let mid = (self.positions[e.0] + self.positions[e.1]) * 0.5;
+        smooth * (1. - weight) + mid * weight
+    }
+
+    /// Creases of the two halves of every parent edge, one level less sharp
+    fn child_creases(
+        &self,
+        edge_points: &HashMap<(usize, usize), usize>,
+    ) -> HashMap<(usize, usize), f32> {
+        let mut creases = HashMap::new();
+        for (&(a, b), &s) in &self.creases {
+            if let Some(&mid) = edge_points.get(&(a, b)) {
+                if s > 1. {
+                    creases.insert(edge(a, mid), s - 1.);
+                    creases.insert(edge(mid, b), s - 1.);
+                }
+            }
+        }
+        creases
+    }
+
+    fn loop_step(&self) -> Self {
+        let topo = self.topology();
+        let p = &self.positions;
+
+        let mut positions: Vec<point3> = (0..p.len())
+            .map(|v| {
+                let n = topo.neighbors[v].len();
+                if n == 0 {
+                    return p[v];
+                }
+                let beta = if n == 3 {
+                    3. / 16.
+                } else {
+                    3. / (8. * n as f32)
+                };
+                let sum = topo.neighbors[v]
+                    .iter()
+                    .fold(Vec3::new(), |acc, &q| acc + p[q]);
+                self.vertex_point(&topo, v, p[v] * (1. - n as f32 * beta) + sum * beta)
+            })
+            .collect();
+
+        let mut edge_points = HashMap::new();
+        for (&e, faces) in &topo.edge_faces {
+            let opposite = |f: usize| {
+                let face = &self.faces[f];
+                face.iter().copied().find(|&c| c != e.0 && c != e.1)
+            };
+            let smooth = match (
+                faces.len(),
+                opposite(faces[0]),
+                faces.get(1).and_then(|&f| opposite(f)),
+            ) {
+                (2, Some(c), Some(d)) => (p[e.0] + p[e.1]) * (3. / 8.) + (p[c] + p[d]) * (1. / 8.),
+                _ => (p[e.0] + p[e.1]) * 0.5,
+            };
+            edge_points.insert(e, positions.len());
+            positions.push(self.edge_point(&topo, e, smooth));
+        }
+
+        let mut faces = vec![];
+        for face in &self.faces {
+            let (a, b, c) = (face[0], face[1], face[2]);
+            let (ab, bc, ca) = (
+                edge_points[&edge(a, b)],
+                edge_points[&edge(b, c)],
+                edge_points[&edge(c, a)],
+            );
+            faces.push(vec![a, ab, ca]);
+            faces.push(vec![b, bc, ab]);
+            faces.push(vec![c, ca, bc]);
+            faces.push(vec![ab, bc, ca]);
+        }
+
+        Self {
+            creases: self.child_creases(&edge_points),
+            positions,
+            faces,
+        }
+    }
+
+    fn catmull_clark_step(&self) -> Self {
+        let topo = self.topology();
+        let p = &self.positions;
+
+        let face_points: Vec<point3> = self
+            .faces
+            .iter()
+            .map(|face| face.iter().fold(Vec3::new(), |acc, &v| acc + p[v]) / face.len() as f32)
+            .collect();
+
+        let mut positions: Vec<point3> = (0..p.len())
+            .map(|v| {
+                let n = topo.neighbors[v].len();
+                let faces = &topo.vertex_faces[v];
+                if n < 3 || faces.is_empty() {
+                    return p[v];
+                }
+                let f = faces
+                    .iter()
+                    .fold(Vec3::new(), |acc, &f| acc + face_points[f])
+                    / faces.len() as f32;
+                let r = topo.neighbors[v]
+                    .iter()
+                    .fold(Vec3::new(), |acc, &q| acc + (p[v] + p[q]) * 0.5)
+                    / n as f32;
+                let smooth = (f + r * 2. + p[v] * (n as f32 - 3.)) / n as f32;
+                self.vertex_point(&topo, v, smooth)
+            })
+            .collect();
+
+        let first_face_point = positions.len();
+        positions.extend(face_points.iter().copied());
+
+        let mut edge_points = HashMap::new();
+        for (&e, faces) in &topo.edge_faces {
+            let smooth = if faces.len() == 2 {
+                (p[e.0] + p[e.1] + face_points[faces[0]] + face_points[faces[1]]) * 0.25
+            } else {
+                (p[e.0] + p[e.1]) * 0.5
+            };
+            edge_points.insert(e, positions.len());
+            positions.push(self.edge_point(&topo, e, smooth));
+        }
+
+        let mut faces = vec![];
+        for (f, face) in self.faces.iter().enumerate() {
+            let n = face.len();
+            for k in 0..n {
+                let (prev, v, next) = (face[(k + n - 1) % n], face[k], face[(k + 1) % n]);
+                faces.push(vec![
+                    v,
+                    edge_points[&edge(v, next)],
+                    first_face_point + f,
+                    edge_points[&edge(prev, v)],
+                ]);
+            }
+        }
+
+        Self {
+            creases: self.child_creases(&edge_points),
+            positions,
+            faces,
+        }
+    }
+
+    /// Faces around `v` in winding order as `(face, corner)`, `None` unless they close a loop
+    fn ring(&self, topo: &Topology, v: usize) -> Option<Vec<(usize, usize)>> {
+        let corner_of = |f: usize| self.faces[f].iter().position(|&c| c == v);
+        let around = &topo.vertex_faces[v];
+        let first = *around.first()?;
+        let mut ring = vec![(first, corner_of(first)?)];
+        loop {
+            let (f, k) = ring[ring.len() - 1];
+            let face = &self.faces[f];
+            let prev = face[(k + face.len() - 1) % face.len()];
+
+            // The next face continues over the edge towards `prev`
+            let next = around.iter().copied().find(|&g| {
+                let other = &self.faces[g];
+                corner_of(g).is_some_and(|c| other[(c + 1) % other.len()] == prev)
+            })?;
+            if next == first {
+                break;
+            }
+            if ring.len() > around.len() {
+                return None;
+            }
+            ring.push((next, corner_of(next)?));
+        }
+        if ring.len() == around.len() {
+            Some(ring)
+        } else {
+            None
+        }
+    }
+
+    /// Pushes the vertices to the limit surface and computes its normals where the scheme
+    /// allows it, falling back to averaged face normals on creases and irregular faces
+    fn limit_mesh(&self, sides: usize) -> Mesh {
+        let topo = self.topology();
+        let p = &self.positions;
+        let averaged = self.triangulate().smooth_normals().normals;
+
+        let mut positions = p.clone();
+        let mut normals = averaged.clone();
+        for v in 0..p.len() {
+            let sharp: Vec<usize> = topo.neighbors[v]
+                .iter()
+                .copied()
+                .filter(|&n| self.sharpness(&topo, edge(v, n)) > 0.)
+                .collect();
+            if sharp.len() == 2 {
+                positions[v] = (p[sharp[0]] + p[v] * 4. + p[sharp[1]]) / 6.;
+            }
+            if !sharp.is_empty() {
+                continue;
+            }
+            let ring = match self.ring(&topo, v) {
+                Some(ring) if ring.iter().all(|&(f, _)| self.faces[f].len() == sides) => ring,
+                _ => continue,
+            };
+
+            let n = ring.len();
+            let angle = |i: usize| 2. * PI * i as f32 / n as f32;
+            let corner =
+                |(f, k): (usize, usize), offset: usize| p[self.faces[f][(k + offset) % sides]];
+            let (mut t1, mut t2) = (Vec3::new(), Vec3::new());
+            if sides == 3 {
+                let beta = if n == 3 {
+                    3. / 16.
+                } else {
+                    3. / (8. * n as f32)
+                };
+                let omega = 3. / (8. * beta);
+                let mut sum = Vec3::new();
+                for (i, &c) in ring.iter().enumerate() {
+                    let q = corner(c, 1);
+                    sum = sum + q;
+                    t1 = t1 + q * angle(i).cos();
+                    t2 = t2 + q * angle(i).sin();
+                }
+                positions[v] = (p[v] * omega + sum) / (omega + n as f32);
+            } else {
+                let a = 1.
+                    + angle(1).cos()
+                    + (PI / n as f32).cos() * (2. * (9. + angle(1).cos())).sqrt();
+                let (mut edges, mut diagonals) = (Vec3::new(), Vec3::new());
+                for (i, &c) in ring.iter().enumerate() {
+                    let (e, f) = (corner(c, 1), corner(c, 2));
+                    edges = edges + e;
+                    diagonals = diagonals + f;
+                    t1 = t1 + e * (a * angle(i).cos()) + f * (angle(i).cos() + angle(i + 1).cos());
+                    t2 = t2 + e * (a * angle(i).sin()) + f * (angle(i).sin() + angle(i + 1).sin());
+                }
+                let nf = n as f32;
+                positions[v] = (p[v] * (nf * nf) + edges * 4. + diagonals) / (nf * (nf + 5.));
+            }
+
+            let normal = cross(t1, t2);
+            if !normal.near_zero() {
+                let normal = unit_vector(normal);
+                normals[v] = if dot(normal, averaged[v]) < 0. {
+                    normal.inv()
+                } else {
+                    normal
+                };
+            }
+        }
+
+        Mesh {
+            positions,
+            ..self.triangulate()
+        }
+        .with_normals(normals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube() -> PolyMesh {
+        let positions = (0..8)
+            .map(|i| point3::from((i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32))
+            .collect();
+        let faces = vec![
+            vec![0, 2, 3, 1],
+            vec![4, 5, 7, 6],
+            vec![0, 1, 5, 4],
+            vec![2, 6, 7, 3],
+            vec![0, 4, 6, 2],
+            vec![1, 3, 7, 5],
+        ];
+        PolyMesh::from(positions, faces)
+    }
+
+    fn assert_outward(mesh: &Mesh) {
+        let center = point3::from(0.5, 0.5, 0.5);
+        for (p, n) in mesh.positions.iter().zip(&mesh.normals) {
+            assert!(dot(*n, *p - center) > 0., "normal {:?} at {:?}", n, p);
+        }
+    }
+
+    #[test]
+    fn catmull_clark_cube_is_closed_and_smooth() {
+        let mesh = cube().catmull_clark(3);
+        assert_eq!(mesh.indices.len(), 6 * 64 * 2);
+        assert_outward(&mesh);
+
+        // The limit surface of a unit cube stays well inside it, with no vertex at a corner
+        for p in &mesh.positions {
+            assert!((*p - point3::from(0.5, 0.5, 0.5)).len() < 0.5 * 3f32.sqrt() - 0.1);
+        }
+    }
+
+    #[test]
+    fn loop_cube_is_closed() {
+        let mesh = cube().loop_subdivide(2);
+        assert_eq!(mesh.indices.len(), 12 * 16);
+        assert_outward(&mesh);
+    }
+
+    #[test]
+    fn creases_keep_sharp_edges() {
+        // The outline of the z = 0 face stays in its plane under both schemes, which a smooth
+        // cube never touches
+        let mut creased = cube();
+        for &(a, b) in &[(0, 1), (1, 3), (3, 2), (2, 0)] {
+            creased = creased.with_crease(a, b, f32::INFINITY);
+        }
+        let on_crease = |mesh: &Mesh| mesh.positions.iter().filter(|p| p.z() == 0.).count();
+
+        let mesh = creased.catmull_clark(2);
+        assert_outward(&mesh);
+        assert!(mesh.positions.iter().any(|p| p.z() == 0. && p.x() == 0.5));
+        assert_eq!(on_crease(&cube().catmull_clark(2)), 0);
+
+        let mesh = creased.loop_subdivide(2);
+        assert_outward(&mesh);
+        assert!(on_crease(&mesh) > 0);
+        assert_eq!(on_crease(&cube().loop_subdivide(2)), 0);
+    }
+
+    #[test]
+    fn faces_of_fewer_than_three_vertices_are_dropped() {
+        let mut faces = cube().faces;
+        faces.push(vec![]);
+        faces.push(vec![0, 1]);
+        let mesh = PolyMesh::from(cube().positions, faces);
+        assert_eq!(mesh.faces.len(), 6);
+        assert_eq!(mesh.triangulate().indices.len(), 12);
+    }
+}
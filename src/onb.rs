@@ -0,0 +1,40 @@
+use crate::vec3::*;
+
+/// Orthonormal basis, used to express sampled directions relative to a normal or ray
+#[derive(Copy, Clone)]
+pub struct Onb {
+  axis: [Vec3; 3],
+}
+
+impl Onb {
+  pub fn build_from_w(n: Vec3) -> Self {
+    let w = unit_vector(n);
+    let a = if w.x().abs() > 0.9 {
+      Vec3::from(0., 1., 0.)
+    } else {
+      Vec3::from(1., 0., 0.)
+    };
+    let v = unit_vector(cross(w, a));
+    let u = cross(w, v);
+    Self { axis: [u, v, w] }
+  }
+  pub fn u(&self) -> Vec3 {
+    self.axis[0]
+  }
+  pub fn v(&self) -> Vec3 {
+    self.axis[1]
+  }
+  pub fn w(&self) -> Vec3 {
+    self.axis[2]
+  }
+  pub fn local(&self, a: f32, b: f32, c: f32) -> Vec3 {
+    self.u() * a + self.v() * b + self.w() * c
+  }
+  pub fn local_vec(&self, a: Vec3) -> Vec3 {
+    self.local(a.x(), a.y(), a.z())
+  }
+  /// Coordinates of the world vector `a` in this basis, the inverse of `local_vec`
+  pub fn to_local(&self, a: Vec3) -> Vec3 {
+    Vec3::from(dot(a, self.u()), dot(a, self.v()), dot(a, self.w()))
+  }
+}
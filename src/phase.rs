@@ -0,0 +1,160 @@
+use crate::{onb::*, utils::*, vec3::*};
+use std::f32::consts::PI;
+
+/// Angular distribution of light scattered inside a participating medium. Directions are
+/// unit vectors, `wi` being the propagation direction of the incoming light
+pub trait PhaseFunction: Send + Sync {
+  /// Value of the phase function, which integrates to one over the sphere
+  fn p(&self, wi: Vec3, wo: Vec3) -> f32;
+  /// Samples an outgoing direction, returning it with its probability density
+  fn sample(&self, wi: Vec3) -> (Vec3, f32);
+}
+
+/// Builds a direction at `cos_theta` from `wi` with a uniformly random azimuth
+fn direction_around(wi: Vec3, cos_theta: f32) -> Vec3 {
+  let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+  let phi = 2. * PI * random_double(0., 1.);
+  Onb::build_from_w(wi).local(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+pub struct IsotropicPhase;
+
+impl PhaseFunction for IsotropicPhase {
+  fn p(&self, _wi: Vec3, _wo: Vec3) -> f32 {
+    1. / (4. * PI)
+  }
+  fn sample(&self, wi: Vec3) -> (Vec3, f32) {
+    (
+      direction_around(wi, 1. - 2. * random_double(0., 1.)),
+      1. / (4. * PI),
+    )
+  }
+}
+
+/// Positive `g` scatters forward (haze, clouds), negative `g` backward
+pub struct HenyeyGreenstein {
+  g: f32,
+}
+
+impl HenyeyGreenstein {
+  pub fn from(g: f32) -> Self {
+    Self {
+      g: clamp(g, -0.99, 0.99),
+    }
+  }
+  fn eval(&self, cos_theta: f32) -> f32 {
+    let denom = 1. + self.g * self.g - 2. * self.g * cos_theta;
+    (1. - self.g * self.g) / (4. * PI * denom * denom.sqrt())
+  }
+  fn sample_cos_theta(&self) -> f32 {
+    let xi = random_double(0., 1.);
+    if self.g.abs() < 1e-3 {
+      return 1. - 2. * xi;
+    }
+    let g = self.g;
+    let sqr_term = (1. - g * g) / (1. - g + 2. * g * xi);
+    clamp((1. + g * g - sqr_term * sqr_term) / (2. * g), -1., 1.)
+  }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+  fn p(&self, wi: Vec3, wo: Vec3) -> f32 {
+    self.eval(dot(wi, wo))
+  }
+  fn sample(&self, wi: Vec3) -> (Vec3, f32) {
+    let cos_theta = self.sample_cos_theta();
+    (direction_around(wi, cos_theta), self.eval(cos_theta))
+  }
+}
+
+/// Blend of a forward and a backward lobe, `weight` going to the first one
+pub struct DoubleHenyeyGreenstein {
+  first: HenyeyGreenstein,
+  second: HenyeyGreenstein,
+  weight: f32,
+}
+
+impl DoubleHenyeyGreenstein {
+  pub fn from(g1: f32, g2: f32, weight: f32) -> Self {
+    Self {
+      first: HenyeyGreenstein::from(g1),
+      second: HenyeyGreenstein::from(g2),
+      weight: clamp(weight, 0., 1.),
+    }
+  }
+  fn eval(&self, cos_theta: f32) -> f32 {
+    self.weight * self.first.eval(cos_theta) + (1. - self.weight) * self.second.eval(cos_theta)
+  }
+}
+
+impl PhaseFunction for DoubleHenyeyGreenstein {
+  fn p(&self, wi: Vec3, wo: Vec3) -> f32 {
+    self.eval(dot(wi, wo))
+  }
+  fn sample(&self, wi: Vec3) -> (Vec3, f32) {
+    let cos_theta = if random_double(0., 1.) < self.weight {
+      self.first.sample_cos_theta()
+    } else {
+      self.second.sample_cos_theta()
+    };
+    (direction_around(wi, cos_theta), self.eval(cos_theta))
+  }
+}
+
+/// Scattering by particles much smaller than the wavelength, such as air molecules
+pub struct Rayleigh;
+
+impl Rayleigh {
+  fn eval(cos_theta: f32) -> f32 {
+    3. / (16. * PI) * (1. + cos_theta * cos_theta)
+  }
+}
+
+impl PhaseFunction for Rayleigh {
+  fn p(&self, wi: Vec3, wo: Vec3) -> f32 {
+    Self::eval(dot(wi, wo))
+  }
+  fn sample(&self, wi: Vec3) -> (Vec3, f32) {
+    // Analytic inversion of the cumulative distribution of cos(theta)
+    let z = 2. * (2. * random_double(0., 1.) - 1.);
+    let u = (z + (z * z + 1.).sqrt()).cbrt();
+    let cos_theta = clamp(u - 1. / u, -1., 1.);
+    (direction_around(wi, cos_theta), Self::eval(cos_theta))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn mean_cosine(phase: &dyn PhaseFunction, power: i32) -> f32 {
+    let wi = Vec3::from(0., 0., 1.);
+    let n = 100000;
+    (0..n)
+      .map(|_| dot(wi, phase.sample(wi).0).powi(power))
+      .sum::<f32>()
+      / n as f32
+  }
+
+  #[test]
+  fn henyey_greenstein_mean_cosine_is_g() {
+    assert!((mean_cosine(&HenyeyGreenstein::from(0.7), 1) - 0.7).abs() < 0.01);
+    assert!((mean_cosine(&HenyeyGreenstein::from(-0.4), 1) + 0.4).abs() < 0.01);
+  }
+
+  #[test]
+  fn rayleigh_sampling_matches_distribution() {
+    assert!(mean_cosine(&Rayleigh, 1).abs() < 0.01);
+    assert!((mean_cosine(&Rayleigh, 2) - 0.4).abs() < 0.01);
+  }
+
+  #[test]
+  fn sampled_pdf_matches_value() {
+    let wi = unit_vector(Vec3::from(1., 2., 3.));
+    let phase = DoubleHenyeyGreenstein::from(0.8, -0.5, 0.6);
+    for _ in 0..100 {
+      let (wo, pdf) = phase.sample(wi);
+      assert!((phase.p(wi, wo) - pdf).abs() < 1e-3 * pdf.max(1.));
+    }
+  }
+}
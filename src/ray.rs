@@ -1,4 +1,6 @@
+use crate::material::material::transmittance;
 use crate::objects::hittable::*;
+use crate::spectrum::*;
 use crate::vec3::*;
 
 #[derive(Copy, Clone)]
@@ -6,6 +8,8 @@ pub struct Ray {
     pub orig: point3,
     pub dir: Vec3,
     pub tm: f32,
+    /// Hero wavelength in nanometers when rendering spectrally, zero otherwise
+    pub wavelength: f32,
 }
 impl Ray {
     pub fn new(origin: point3, direction: Vec3, time: f32) -> Self {
@@ -13,8 +17,13 @@ impl Ray {
             orig: origin,
             dir: direction,
             tm: time,
+            wavelength: 0.,
         }
     }
+    pub fn with_wavelength(mut self, wavelength: f32) -> Self {
+        self.wavelength = wavelength;
+        self
+    }
     pub fn origin(&self) -> point3 {
         self.orig
     }
@@ -24,31 +33,122 @@ impl Ray {
     pub fn time(&self) -> f32 {
         self.tm
     }
+    pub fn wavelength(&self) -> f32 {
+        self.wavelength
+    }
     pub fn at(&self, t: f32) -> point3 {
         self.orig + self.dir * t
     }
 }
 
 pub fn ray_color(r: Ray, background: &color, world: &impl Hittable, depth: i32) -> color {
-    let mut rec = HitRecord::void();
-
     if depth <= 0 {
         return color::new();
     }
 
-    if !world.hit(&r, 0.001, f32::INFINITY, &mut rec) {
-        return *background;
-    }
-    let mut scattered = Ray::new(point3::new(), Vec3::new(), 0.);
-    let mut attenuation = color::new();
+    let rec = match world.hit(&r, 0.001, f32::INFINITY) {
+        Some(rec) => rec,
+        None => return *background,
+    };
     let emitted = rec.mat_ptr.emitted(rec.u, rec.v, &rec.p);
 
-    if !rec
-        .mat_ptr
-        .scatter(r, rec, &mut attenuation, &mut scattered)
-    {
-        return emitted;
+    let radiance = match rec.mat_ptr.scatter(&r, &rec) {
+        Some(srec) => {
+            emitted + srec.attenuation * ray_color(srec.scattered, background, world, depth - 1)
+        }
+        None => emitted,
+    };
+
+    // Reaching a back face, the ray has just crossed the inside of the object. No medium is
+    // tracked, so rays starting inside one object and ending on another's back face, as with
+    // nested or overlapping bodies, are attenuated by the wrong material
+    if rec.front_face {
+        radiance
+    } else {
+        let distance = rec.t * r.direction().len();
+        radiance * transmittance(rec.mat_ptr.absorption(), distance)
     }
+}
+
+/// Spectral counterpart of `ray_color`, following the hero of `wavelengths`. RGB colors of
+/// materials and the background are upsampled to spectra
+pub fn ray_spectrum(
+    r: Ray,
+    background: &color,
+    world: &impl Hittable,
+    depth: i32,
+    wavelengths: &mut SampledWavelengths,
+) -> SampledSpectrum {
+    if depth <= 0 {
+        return SampledSpectrum::new();
+    }
+
+    let r = r.with_wavelength(wavelengths.hero());
+    let rec = match world.hit(&r, 0.001, f32::INFINITY) {
+        Some(rec) => rec,
+        None => return SampledSpectrum::from_rgb(*background, wavelengths),
+    };
+    let emitted = SampledSpectrum::from_rgb(rec.mat_ptr.emitted(rec.u, rec.v, &rec.p), wavelengths);
+
+    let radiance = match rec.mat_ptr.scatter(&r, &rec) {
+        Some(srec) => {
+            if rec.mat_ptr.is_dispersive() {
+                wavelengths.terminate_secondary();
+            }
+            let attenuation = SampledSpectrum::from_rgb(srec.attenuation, wavelengths);
+            emitted
+                + attenuation
+                    * ray_spectrum(srec.scattered, background, world, depth - 1, wavelengths)
+        }
+        None => emitted,
+    };
 
-    emitted + attenuation * ray_color(scattered, background, world, depth - 1)
+    if rec.front_face {
+        radiance
+    } else {
+        let distance = rec.t * r.direction().len();
+        let sigma = SampledSpectrum::from_rgb(rec.mat_ptr.absorption(), wavelengths);
+        radiance * (sigma * -distance).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::material::*;
+    use crate::objects::plane::Plane;
+    use std::sync::Arc;
+
+    /// White inside surface that absorbs along the way to it
+    struct Absorbing(color);
+
+    impl Material for Absorbing {
+        fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+            None
+        }
+        fn emitted(&self, _u: f32, _v: f32, _p: &point3) -> color {
+            color::from(1., 1., 1.)
+        }
+        fn absorption(&self) -> color {
+            self.0
+        }
+    }
+
+    #[test]
+    fn slab_transmits_exp_of_minus_sigma_d() {
+        let sigma = color::from(0.1, 0.5, 2.);
+        for &d in &[0.5, 1., 3.] {
+            // The ray leaves the slab through the back of its top face
+            let slab = Plane::from(
+                point3::from(0., d, 0.),
+                Vec3::from(0., 1., 0.),
+                Arc::new(Absorbing(sigma)),
+            );
+            let r = Ray::new(point3::new(), Vec3::from(0., 2., 0.), 0.);
+            let radiance = ray_color(r, &color::new(), &slab, 2);
+            for a in 0..3 {
+                assert!((radiance[a] - (-sigma[a] * d).exp()).abs() < 1e-5);
+            }
+        }
+    }
 }
@@ -0,0 +1,185 @@
+use crate::{utils::*, vec3::*};
+use std::ops;
+
+pub const LAMBDA_MIN: f32 = 360.;
+pub const LAMBDA_MAX: f32 = 830.;
+/// Wavelengths carried by each path: the hero and the ones evenly spread after it
+pub const N_WAVELENGTHS: usize = 4;
+/// Integral of the CIE ȳ matching function over the visible range, so a flat unit spectrum
+/// has a luminance Y of one
+const CIE_Y_INTEGRAL: f32 = 106.856895;
+
+#[derive(Copy, Clone, Debug)]
+pub struct SampledWavelengths {
+    pub lambda: [f32; N_WAVELENGTHS],
+    secondary_terminated: bool,
+}
+
+impl SampledWavelengths {
+    pub fn sample() -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = random_double(0., range);
+        let mut lambda = [0.; N_WAVELENGTHS];
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (hero + i as f32 * range / N_WAVELENGTHS as f32) % range;
+            *l = LAMBDA_MIN + offset;
+        }
+        Self {
+            lambda,
+            secondary_terminated: false,
+        }
+    }
+    pub fn hero(&self) -> f32 {
+        self.lambda[0]
+    }
+    /// Keeps only the hero, after an event such as dispersion that bends each wavelength its
+    /// own way
+    pub fn terminate_secondary(&mut self) {
+        self.secondary_terminated = true;
+    }
+
+    /// CIE XYZ of the spectrum `s` measured at these wavelengths
+    pub fn to_xyz(&self, s: &SampledSpectrum) -> color {
+        // Each wavelength is drawn uniformly over the range, with density 1 / range
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut xyz = color::new();
+        for i in 0..N_WAVELENGTHS {
+            let weight = if !self.secondary_terminated {
+                1. / N_WAVELENGTHS as f32
+            } else if i == 0 {
+                1.
+            } else {
+                0.
+            };
+            xyz = xyz + cie_xyz(self.lambda[i]) * (s.values[i] * weight * range);
+        }
+        xyz / CIE_Y_INTEGRAL
+    }
+}
+
+/// Values of a spectrum at the `SampledWavelengths` of a path
+#[derive(Copy, Clone, Debug)]
+pub struct SampledSpectrum {
+    pub values: [f32; N_WAVELENGTHS],
+}
+
+impl SampledSpectrum {
+    pub fn new() -> Self {
+        Self::constant(0.)
+    }
+    pub fn constant(x: f32) -> Self {
+        Self {
+            values: [x; N_WAVELENGTHS],
+        }
+    }
+    /// Smooth spectrum reproducing `c`, flat when the three channels are equal
+    pub fn from_rgb(c: color, wavelengths: &SampledWavelengths) -> Self {
+        let mut values = [0.; N_WAVELENGTHS];
+        for (v, &l) in values.iter_mut().zip(wavelengths.lambda.iter()) {
+            let red = smoothstep(570., 610., l);
+            let blue = 1. - smoothstep(470., 510., l);
+            let green = 1. - red - blue;
+            *v = c.x() * red + c.y() * green + c.z() * blue;
+        }
+        Self { values }
+    }
+    pub fn exp(&self) -> Self {
+        let mut values = self.values;
+        values.iter_mut().for_each(|v| *v = v.exp());
+        Self { values }
+    }
+}
+
+impl Default for SampledSpectrum {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ops::Add for SampledSpectrum {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(other.values.iter()) {
+            *v += o;
+        }
+        Self { values }
+    }
+}
+
+impl ops::Mul for SampledSpectrum {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let mut values = self.values;
+        for (v, o) in values.iter_mut().zip(other.values.iter()) {
+            *v *= o;
+        }
+        Self { values }
+    }
+}
+
+impl ops::Mul<f32> for SampledSpectrum {
+    type Output = Self;
+    fn mul(self, t: f32) -> Self {
+        let mut values = self.values;
+        values.iter_mut().for_each(|v| *v *= t);
+        Self { values }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = clamp((x - edge0) / (edge1 - edge0), 0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+/// CIE 1931 color matching functions, from the multi-lobe fit of Wyman, Sloan and Shirley
+pub fn cie_xyz(lambda: f32) -> color {
+    let g = |mu: f32, s1: f32, s2: f32| {
+        let t = (lambda - mu) / if lambda < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+    color::from(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+/// Linear sRGB of `xyz`, with the white of a flat spectrum mapped to the sRGB white
+pub fn xyz_to_srgb(xyz: color) -> color {
+    // Von Kries scaling from the equal-energy white to D65
+    let (x, y, z) = (xyz.x() * 0.95047, xyz.y(), xyz.z() * 1.08883);
+    color::from(
+        (3.240454 * x - 1.537139 * y - 0.4985314 * z).max(0.),
+        (-0.969266 * x + 1.876011 * y + 0.041556 * z).max(0.),
+        (0.0556434 * x - 0.2040259 * y + 1.057225 * z).max(0.),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_spectrum_is_white() {
+        let mut total = color::new();
+        let n = 20_000;
+        for _ in 0..n {
+            let wavelengths = SampledWavelengths::sample();
+            let s = SampledSpectrum::from_rgb(color::from(0.5, 0.5, 0.5), &wavelengths);
+            total = total + xyz_to_srgb(wavelengths.to_xyz(&s));
+        }
+        let mean = total / n as f32;
+        for c in 0..3 {
+            assert!((mean.e[c] - 0.5).abs() < 0.02, "{:?}", mean);
+        }
+
+        // The hero alone carries the whole estimate once the others are dropped
+        let mut wavelengths = SampledWavelengths::sample();
+        let s = SampledSpectrum::constant(1.);
+        wavelengths.terminate_secondary();
+        let hero = wavelengths.to_xyz(&s);
+        let expected = cie_xyz(wavelengths.hero()) * ((LAMBDA_MAX - LAMBDA_MIN) / CIE_Y_INTEGRAL);
+        assert!((hero - expected).len() < 1e-4);
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{
+  aabb::*, constant_medium::*, material::material::*, material::microfacet::*,
+  objects::hittable::*, phase::*, ray::*, utils::*, vec3::*,
+};
+use std::sync::Arc;
+
+/// Collisions after which a path still wandering inside is dropped
+const MAX_STEPS: usize = 1024;
+
+/// Translucent solid such as skin, wax, marble or milk. Light refracts through a smooth
+/// boundary into a scattering medium and random-walks inside, with distances drawn as in
+/// `ConstantMedium`, until it leaves the boundary again
+pub struct Subsurface {
+  boundary: Arc<dyn Hittable>,
+  sigma_s: color,
+  sigma_t: color,
+  phase: Arc<dyn PhaseFunction>,
+  ir: f32,
+}
+
+impl Subsurface {
+  /// Medium of scattering and absorption coefficients `sigma_s` and `sigma_a` per unit length
+  pub fn from_coefficients(b: Arc<dyn Hittable>, sigma_s: color, sigma_a: color, ir: f32) -> Self {
+    Self {
+      boundary: b,
+      sigma_s,
+      sigma_t: sigma_s + sigma_a,
+      phase: Arc::new(IsotropicPhase),
+      ir,
+    }
+  }
+  /// Medium looking like `albedo` from afar, light travelling about `mean_free_path` between
+  /// collisions in each channel
+  pub fn from(b: Arc<dyn Hittable>, albedo: color, mean_free_path: color, ir: f32) -> Self {
+    // Single-scattering albedo giving `albedo` after multiple scattering (van de Hulst)
+    let invert = |a: f32| {
+      let a = clamp(a, 0., 0.999);
+      let x = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+      1. - x * x
+    };
+    let sigma_t = color::from(
+      1. / mean_free_path.x(),
+      1. / mean_free_path.y(),
+      1. / mean_free_path.z(),
+    );
+    let single = color::from(invert(albedo.x()), invert(albedo.y()), invert(albedo.z()));
+    Self::from_coefficients(
+      b,
+      single * sigma_t,
+      (color::from(1., 1., 1.) - single) * sigma_t,
+      ir,
+    )
+  }
+  pub fn with_phase(mut self, phase: Arc<dyn PhaseFunction>) -> Self {
+    self.phase = phase;
+    self
+  }
+
+  /// Refracts out a walk reaching the boundary along `d`, or reflects it back inside
+  fn cross_boundary(&self, d: Vec3, outward_normal: Vec3) -> Result<Vec3, Vec3> {
+    let cos_i = dot(d, outward_normal);
+    if fresnel_dielectric(cos_i, 1. / self.ir) > random_double(0., 1.) {
+      Err(reflect(d, outward_normal))
+    } else {
+      Ok(unit_vector(refract(d, outward_normal.inv(), self.ir)))
+    }
+  }
+}
+
+impl Hittable for Subsurface {
+  fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+    let rec = self.boundary.hit(r, t_min, t_max)?;
+    Some(HitRecord {
+      mat_ptr: self,
+      ..rec
+    })
+  }
+  fn bounding_box(&self, time0: f32, time1: f32) -> Option<Aabb> {
+    self.boundary.bounding_box(time0, time1)
+  }
+}
+
+impl Material for Subsurface {
+  fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+    let wi = unit_vector(r_in.direction());
+    let time = r_in.time();
+
+    let mut d = if rec.front_face {
+      if fresnel_dielectric(dot(wi.inv(), rec.normal), self.ir) > random_double(0., 1.) {
+        return Some(ScatterRecord::specular(
+          color::from(1., 1., 1.),
+          Ray::new(rec.p, reflect(wi, rec.normal), time),
+        ));
+      }
+      unit_vector(refract(wi, rec.normal, 1. / self.ir))
+    } else {
+      // A path starting inside meets the boundary first
+      match self.cross_boundary(wi, rec.normal.inv()) {
+        Ok(out) => {
+          return Some(ScatterRecord::diffuse(
+            color::from(1., 1., 1.),
+            Ray::new(rec.p, out, time),
+          ))
+        }
+        Err(reflected) => reflected,
+      }
+    };
+
+    // Distances are drawn from one channel, picked in proportion to its throughput, and
+    // weighted by the mixture of the channel densities so every channel stays unbiased
+    let mut p = rec.p;
+    let mut throughput = color::from(1., 1., 1.);
+    for _ in 0..MAX_STEPS {
+      let exit = self
+        .boundary
+        .hit(&Ray::new(p, d, time), 1e-4, f32::INFINITY)?;
+      let total = throughput.x() + throughput.y() + throughput.z();
+      if total <= 0. {
+        return None;
+      }
+      let weights = throughput / total;
+      let u = random_double(0., 1.);
+      let channel = if u < weights.x() {
+        0
+      } else if u < weights.x() + weights.y() {
+        1
+      } else {
+        2
+      };
+      let t = sample_free_path(-1. / self.sigma_t.e[channel]);
+      let mixture = |c: color| dot(weights, c);
+
+      if t < exit.t {
+        let tr = transmittance(self.sigma_t, t);
+        throughput = throughput * self.sigma_s * tr / mixture(self.sigma_t * tr);
+        p = p + d * t;
+
+        let (direction, pdf) = self.phase.sample(d);
+        if pdf <= 0. {
+          return None;
+        }
+        throughput = throughput * (self.phase.p(d, direction) / pdf);
+        d = direction;
+      } else {
+        let tr = transmittance(self.sigma_t, exit.t);
+        throughput = throughput * tr / mixture(tr);
+        p = exit.p;
+
+        let outward_normal = if exit.front_face {
+          exit.normal
+        } else {
+          exit.normal.inv()
+        };
+        match self.cross_boundary(d, outward_normal) {
+          Ok(out) => return Some(ScatterRecord::diffuse(throughput, Ray::new(p, out, time))),
+          Err(reflected) => d = reflected,
+        }
+      }
+    }
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::objects::sphere::*;
+
+  #[test]
+  fn walk_without_absorption_keeps_the_energy() {
+    let boundary = Arc::new(Sphere::new(
+      point3::new(),
+      1.,
+      Arc::new(Lambertian::from(color::new())),
+    ));
+    let sss = Subsurface::from_coefficients(boundary, color::from(2., 5., 10.), color::new(), 1.3);
+    let r = Ray::new(point3::from(0.3, 0., -5.), Vec3::from(0., 0., 1.), 0.);
+    let rec = sss.hit(&r, 0.001, f32::INFINITY).unwrap();
+    let n = 5000;
+    let mut total = color::new();
+    for _ in 0..n {
+      if let Some(srec) = sss.scatter(&r, &rec) {
+        let o = srec.scattered.origin();
+        assert!((o.len() - 1.).abs() < 1e-3);
+        assert!(dot(srec.scattered.direction(), o) > 0.);
+        total = total + srec.attenuation;
+      }
+    }
+    let mean = total / n as f32;
+    for c in 0..3 {
+      assert!((mean.e[c] - 1.).abs() < 0.05, "{:?}", mean);
+    }
+  }
+}
@@ -35,6 +35,8 @@ impl Texture for SolidColor {
 pub struct CheckerTexture {
   odd: Arc<dyn Texture>,
   even: Arc<dyn Texture>,
+  /// Squares along each of u and v, when checkered in texture space
+  uv_cells: Option<f32>,
 }
 
 impl CheckerTexture {
@@ -42,18 +44,34 @@ impl CheckerTexture {
     Self {
       odd: _odd,
       even: _even,
+      uv_cells: None,
     }
   }
   pub fn from_colors(c1: color, c2: color) -> Self {
     Self {
       even: Arc::new(SolidColor::from(c1)),
       odd: Arc::new(SolidColor::from(c2)),
+      uv_cells: None,
     }
   }
+  /// Checkers the `(u, v)` square in `cells` by `cells` squares instead of space, so that
+  /// flat surfaces lying on the zeros of the sines are checkered too
+  pub fn with_uv_cells(mut self, cells: f32) -> Self {
+    self.uv_cells = Some(cells);
+    self
+  }
 }
 
 impl Texture for CheckerTexture {
   fn value(&self, u: f32, v: f32, p: &point3) -> color {
+    if let Some(cells) = self.uv_cells {
+      let parity = (u * cells).floor() as i64 + (v * cells).floor() as i64;
+      return if parity.rem_euclid(2) == 1 {
+        self.odd.value(u, v, p)
+      } else {
+        self.even.value(u, v, p)
+      };
+    }
     let sines = (10. * p.x()).sin() * (10. * p.y()).sin() * (10. * p.z()).sin();
     if sines < 0. {
       return self.odd.value(u, v, p);
@@ -135,6 +153,9 @@ impl ImageTexture {
       bytes_per_scanline,
     }
   }
+  pub fn dimensions(&self) -> (usize, usize) {
+    (self.width, self.height)
+  }
 }
 
 impl Texture for ImageTexture {
@@ -0,0 +1,231 @@
+use crate::{aabb::*, objects::hittable::*, ray::*};
+
+/// Top-level acceleration structure over instances, each pointing at a bottom-level BVH that
+/// may be shared. Moving instances only requires `rebuild`, the shared BVHs stay untouched
+pub struct Tlas {
+  instances: Vec<Instance>,
+  nodes: Vec<TlasNode>,
+  /// Instances without a bounding box, tested against every ray
+  unbounded: Vec<usize>,
+}
+
+enum TlasNode {
+  Leaf {
+    bbox: Aabb,
+    instance: usize,
+  },
+  Interior {
+    bbox: Aabb,
+    left: usize,
+    right: usize,
+  },
+}
+
+impl TlasNode {
+  fn bbox(&self) -> &Aabb {
+    match self {
+      TlasNode::Leaf { bbox, .. } | TlasNode::Interior { bbox, .. } => bbox,
+    }
+  }
+}
+
+impl Tlas {
+  pub fn from(instances: Vec<Instance>) -> Self {
+    let mut tlas = Self {
+      instances,
+      nodes: vec![],
+      unbounded: vec![],
+    };
+    tlas.rebuild();
+    tlas
+  }
+  pub fn instances(&self) -> &[Instance] {
+    &self.instances
+  }
+  /// Access to move instances around. Call `rebuild` afterwards
+  pub fn instances_mut(&mut self) -> &mut [Instance] {
+    &mut self.instances
+  }
+  pub fn add(&mut self, instance: Instance) {
+    self.instances.push(instance);
+  }
+
+  /// Rebuilds the tree over the current instance boxes
+  pub fn rebuild(&mut self) {
+    self.nodes.clear();
+    self.unbounded.clear();
+
+    let mut leaves = vec![];
+    for (i, instance) in self.instances.iter().enumerate() {
+      match instance.bounding_box(0., 1.) {
+        Some(bbox) => leaves.push((i, bbox)),
+        None => self.unbounded.push(i),
+      }
+    }
+    if !leaves.is_empty() {
+      self.build(&mut leaves);
+    }
+  }
+
+  /// Splits at the median centroid along the widest axis. The root ends up at index 0
+  fn build(&mut self, leaves: &mut [(usize, Aabb)]) -> usize {
+    let index = self.nodes.len();
+    if leaves.len() == 1 {
+      let (instance, bbox) = leaves[0];
+      self.nodes.push(TlasNode::Leaf { bbox, instance });
+      return index;
+    }
+
+    let centroid = |b: &Aabb| (b.min() + b.max()) * 0.5;
+    let mut low = centroid(&leaves[0].1);
+    let mut high = low;
+    let mut bbox = leaves[0].1;
+    for (_, b) in leaves.iter() {
+      let c = centroid(b);
+      for a in 0..3 {
+        low[a] = low[a].min(c[a]);
+        high[a] = high[a].max(c[a]);
+      }
+      bbox = surrounding_box(&bbox, b);
+    }
+    let extent = high - low;
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+      0
+    } else if extent.y() > extent.z() {
+      1
+    } else {
+      2
+    };
+
+    let mid = leaves.len() / 2;
+    leaves.sort_unstable_by(|a, b| {
+      centroid(&a.1)[axis]
+        .partial_cmp(&centroid(&b.1)[axis])
+        .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    // Reserve the slot so that the children land after their parent
+    self.nodes.push(TlasNode::Leaf { bbox, instance: 0 });
+    let (first, second) = leaves.split_at_mut(mid);
+    let left = self.build(first);
+    let right = self.build(second);
+    self.nodes[index] = TlasNode::Interior { bbox, left, right };
+    index
+  }
+
+  fn hit_node(&self, node: usize, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+    let node = &self.nodes[node];
+    if !node.bbox().hit(r, t_min, t_max) {
+      return None;
+    }
+    match node {
+      TlasNode::Leaf { instance, .. } => {
+        let instance = &self.instances[*instance];
+        let rec = instance.hit(r, t_min, t_max)?;
+        validate_hit(instance, r, &rec);
+        Some(rec)
+      }
+      TlasNode::Interior { left, right, .. } => {
+        let hit_left = self.hit_node(*left, r, t_min, t_max);
+        let hit_right = self.hit_node(
+          *right,
+          r,
+          t_min,
+          hit_left.as_ref().map_or(t_max, |rec| rec.t),
+        );
+        hit_right.or(hit_left)
+      }
+    }
+  }
+}
+
+impl Hittable for Tlas {
+  fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord<'_>> {
+    let mut closest = if self.nodes.is_empty() {
+      None
+    } else {
+      self.hit_node(0, r, t_min, t_max)
+    };
+    for &i in &self.unbounded {
+      let t_max = closest.as_ref().map_or(t_max, |rec| rec.t);
+      if let Some(rec) = self.instances[i].hit(r, t_min, t_max) {
+        closest = Some(rec);
+      }
+    }
+    closest
+  }
+
+  fn bounding_box(&self, _time0: f32, _time1: f32) -> Option<Aabb> {
+    if !self.unbounded.is_empty() {
+      return None;
+    }
+    self.nodes.first().map(|node| *node.bbox())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    bvh::*, material::material::*, objects::hittable_list::*, objects::sphere::*, vec3::*,
+  };
+  use std::sync::Arc;
+
+  fn closest_t(instances: &[Instance], r: &Ray) -> Option<f32> {
+    instances
+      .iter()
+      .filter_map(|i| i.hit(r, 0.001, f32::INFINITY))
+      .map(|rec| rec.t)
+      .fold(None, |acc: Option<f32>, t| {
+        Some(acc.map_or(t, |a| a.min(t)))
+      })
+  }
+
+  #[test]
+  fn matches_brute_force_after_rebuild() {
+    let mut spheres = HittableList::new();
+    let white = Arc::new(Lambertian::from(color::from(1., 1., 1.)));
+    for i in 0..10 {
+      spheres.add(Arc::new(Sphere::new(
+        point3::from(i as f32, 0., 0.),
+        0.3,
+        white.clone(),
+      )));
+    }
+    let blas: Arc<dyn Hittable> = Arc::new(BvhNode::from(&mut spheres, 0., 1.));
+
+    let instances = (0..50)
+      .map(|i| {
+        let up = Vec3::random(-1., 1.) + Vec3::from(0., 2., 0.);
+        let offset = Vec3::random(-20., 20.);
+        Instance::from(
+          blas.clone(),
+          up,
+          i as f32 * 7.,
+          0.5 + (i % 3) as f32,
+          offset,
+        )
+      })
+      .collect();
+    let mut tlas = Tlas::from(instances);
+
+    for frame in 0..2 {
+      for _ in 0..500 {
+        let target = Vec3::random(-20., 20.);
+        let r = Ray::new(
+          point3::from(0., 0., -60.),
+          target - point3::from(0., 0., -60.),
+          0.,
+        );
+        let expected = closest_t(tlas.instances(), &r);
+        let found = tlas.hit(&r, 0.001, f32::INFINITY).map(|rec| rec.t);
+        assert_eq!(expected, found, "frame {}", frame);
+      }
+
+      for instance in tlas.instances_mut() {
+        instance.set_placement(Vec3::from(0., 1., 0.), 30., 1., Vec3::random(-20., 20.));
+      }
+      tlas.rebuild();
+    }
+  }
+}